@@ -0,0 +1,44 @@
+use clap::{Parser, Subcommand};
+
+use crate::controllers::planner::PlanTarget;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Preprocesses documents with LLMs for Urška")]
+pub struct Cli {
+    /// Path to the TOML config file (defaults to ./config.toml if present)
+    #[arg(short, long, global = true, env = "DOC_PREP_CONFIG")]
+    pub config: Option<String>,
+
+    /// Override any config key, e.g. `--set generation.temperature=0.2`
+    #[arg(short, long = "set", value_name = "KEY=VALUE", global = true)]
+    pub overrides: Vec<String>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Translate documents from `translate.input_folder`
+    Translate,
+    /// Generate keywords for passages from `decorate.input_folder` and save them
+    Decorate,
+    /// Decorate passages from `decorate.input_folder`, embed them and upsert to Qdrant
+    Embed,
+    /// Generate questions for passages from `questions.input_folder` for embedding fine-tuning
+    GenerateQuestions,
+    /// Report chunk counts, prompt tokens and an ETA without loading any model
+    Plan {
+        /// Which command to plan for
+        #[arg(value_enum, default_value = "translate")]
+        target: PlanTarget,
+    },
+    /// Run the stages of a pipeline spec file, passing data between them in memory
+    Pipeline {
+        /// Path to the pipeline TOML spec
+        spec: String,
+        /// Comma separated names of the stages to run (defaults to all)
+        #[arg(long, value_delimiter = ',')]
+        stages: Vec<String>,
+    },
+}
@@ -1,47 +1,491 @@
-// FUNCTION
-pub const TRANSLATE: bool = false;
-pub const EMBEDD: bool = false;
-pub const DECORATE: bool = true;
-pub const GENERATE_EMBEDDING_QUESTIONS: bool = true;
+use std::{fs, path::Path};
+use anyhow::{Error, Result};
+use once_cell::sync::OnceCell;
+use serde::{Serialize, Deserialize};
+
+use crate::controllers::splitter::{parents::ParentMode, SemanticUnit, SplitStrategy};
+
+pub const DEFAULT_CONFIG_FILE: &str = "./config.toml";
+pub const ENV_PREFIX: &str = "DOC_PREP__";
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+    pub translate: TranslateConfig,
+    pub decorate: DecorateConfig,
+    pub questions: QuestionsConfig,
+    pub embedding: EmbeddingConfig,
+    pub qdrant: QdrantConfig,
+    pub progress: ProgressConfig,
+    pub generation: GenerationConfig,
+    pub plan: PlanConfig,
+    pub loader: LoaderConfig,
+    pub dedup: DedupConfig,
+    pub filter: FilterConfig,
+}
 
 // TRANSLATE
-pub const DOCS_TO_TRANSLATE_FOLDER: &str = "./data/a_to_translate/";
-pub const TRANSLATOR_PROGRESS_FILE: &str = "./data/a_to_translate/translation_progress.json";
-pub const TRANSLATOR_SYSTEM_MSG: &str = "Your task is to translate the given passages from slovene to english. The passages are given in a markdown format. You should keep the structure of the markdown and have the translation to english be as close to the original meaning as possible. It is import you only respond with the transalation and keep the markdown structure.";
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct TranslateConfig {
+    pub input_folder: String,
+    pub progress_file: String,
+    pub manifest_file: String,
+    pub target_language: String, // documents already in this language are not translated
+    pub splitter: SplitterConfig,
+    pub system_msg: String,
+    pub tokenizer: String,
+    pub model: String,
+}
+
+impl Default for TranslateConfig {
+    fn default() -> Self {
+        Self {
+            input_folder: "./data/a_to_translate/".to_string(),
+            progress_file: "./data/a_to_translate/translation_progress.json".to_string(),
+            manifest_file: "./data/a_to_translate/translation_manifest.json".to_string(),
+            target_language: "en".to_string(),
+            // Translated passages are prompted, not embedded
+            splitter: SplitterConfig {
+                tokenizer: Some("models/llama3-8b/tokenizer.json".to_string()),
+                ..SplitterConfig::with_strategy(SplitStrategy::Prompts)
+            },
+            system_msg: "Your task is to translate the given passages from slovene to english. The passages are given in a markdown format. You should keep the structure of the markdown and have the translation to english be as close to the original meaning as possible. It is import you only respond with the transalation and keep the markdown structure.".to_string(),
+            tokenizer: "models/llama3-8b/tokenizer.json".to_string(),
+            model: "models/llama3-8b/Meta-Llama-3-8B-Instruct.Q5_K_M.gguf".to_string(),
+        }
+    }
+}
+
+// DECORATE
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DecorateConfig {
+    pub input_folder: String,
+    pub progress_file: String,
+    pub embed_progress_file: String, // used by the `embed` command
+    pub output_file: String,
+    pub manifest_file: String,
+    pub embed_manifest_file: String, // used by the `embed` command
+    pub splitter: SplitterConfig,
+    pub parents: ParentConfig, // used by the `embed` command
+    pub system_msg: String,
+    pub tokenizer: String,
+    pub model: String,
+}
+
+impl Default for DecorateConfig {
+    fn default() -> Self {
+        Self {
+            input_folder: "./data/processed/".to_string(),
+            progress_file: "./data/processed/decoration_progress.json".to_string(),
+            embed_progress_file: "./data/processed/embedding_progress.json".to_string(),
+            output_file: "./data/processed/decorated.jsonl".to_string(),
+            manifest_file: "./data/processed/decoration_manifest.json".to_string(),
+            embed_manifest_file: "./data/processed/embedding_manifest.json".to_string(),
+            splitter: SplitterConfig::with_strategy(SplitStrategy::Prompts),
+            parents: ParentConfig::default(),
+            system_msg: "Your task is to generate an unordered list of keywords about a given text passage. The passages are given in a markdown format. The passages are part of documents and information about University of Primorska. The keywords should cover what the passage is talking about. Generate up to 5 keywords. If applicable the study programm should be on the list of keywords. For clues you are also given the name of the document that the passage was taken from. The keywords should be generated from the perspective of what the document would mean to the student. It is important you only respond with keywords.".to_string(),
+            tokenizer: "models/llama3-8b/tokenizer.json".to_string(),
+            model: "models/llama3-8b/Meta-Llama-3-8B-Instruct.Q5_K_M.gguf".to_string(),
+        }
+    }
+}
+
+// EMBEDDING QUESTIONS
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct QuestionsConfig {
+    pub input_folder: String,
+    pub progress_file: String,
+    pub output_file: String,
+    pub splitter: SplitterConfig,
+    pub system_msg: String,
+    pub tokenizer: String,
+    pub model: String,
+}
+
+impl Default for QuestionsConfig {
+    fn default() -> Self {
+        Self {
+            input_folder: "./data/processed/".to_string(),
+            progress_file: "./data/processed/questions_progress.json".to_string(),
+            output_file: "./data/processed/embedding_questions.jsonl".to_string(),
+            splitter: SplitterConfig::with_strategy(SplitStrategy::Overlapping),
+            system_msg: "Your task is to generate questions that a student could ask and that are answered by the given text passage. The passages are given in a markdown format. The passages are part of documents and information about University of Primorska. For clues you are also given the name of the document that the passage was taken from. Generate up to 3 questions, each in its own line. It is important you only respond with the questions.".to_string(),
+            tokenizer: "models/llama3-8b/tokenizer.json".to_string(),
+            model: "models/llama3-8b/Meta-Llama-3-8B-Instruct.Q5_K_M.gguf".to_string(),
+        }
+    }
+}
+
+// SPLITTING
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SplitterConfig {
+    pub strategy: SplitStrategy, // "prompts", "overlapping", "markdown" or "semantic"
+    pub chunk_tokens: usize,
+    pub min_chunk_tokens: usize, // "semantic" only, smaller chunks are not split at topic shifts
+    pub semantic_unit: SemanticUnit, // "semantic" only, "paragraph" or "sentence"
+    pub breakpoint_percentile: f64, // "semantic" only, embedding distances from this percentile up split chunks
+    pub overlap_tokens: usize, // "overlapping" only, upper bound of the repeated paragraphs
+    pub overlap_paragraphs: usize, // "overlapping" only
+    pub tokenizer: Option<String>, // used to count tokens, the embedding model's tokenizer and input length if not set
+}
 
+impl Default for SplitterConfig {
+    fn default() -> Self {
+        Self {
+            strategy: SplitStrategy::Prompts,
+            chunk_tokens: 450,
+            min_chunk_tokens: 100,
+            semantic_unit: SemanticUnit::Paragraph,
+            breakpoint_percentile: 90.,
+            overlap_tokens: 100,
+            overlap_paragraphs: 1,
+            tokenizer: None,
+        }
+    }
+}
 
-// EMBEDDING
-pub const DOCS_TO_EMBEDD_FOLDER: &str = "./data/processed/";
-pub const KEYWORD_DECORATOR_PROGRESS_FILE: &str = "./data/processed/decoration_progress.json";
-pub const QDRANT_SERVER: &str = "http://localhost:6334";
-pub const QDRANT_COLLECTION: &str = "urska_md_baai_ft_decorated";
-pub const KEYWORD_DECORATOR_SYSTEM_MSG: &str = "Your task is to generate an unordered list of keywords about a given text passage. The passages are given in a markdown format. The passages are part of documents and information about University of Primorska. The keywords should cover what the passage is talking about. Generate up to 5 keywords. If applicable the study programm should be on the list of keywords. For clues you are also given the name of the document that the passage was taken from. The keywords should be generated from the perspective of what the document would mean to the student. It is important you only respond with keywords.";
+impl SplitterConfig {
+    fn with_strategy(strategy: SplitStrategy) -> Self {
+        Self {
+            strategy,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ParentConfig {
+    pub mode: ParentMode, // "none", "chunks" or "window"
+    pub window: usize, // "window" only, neighbouring chunks on each side
+    pub splitter: SplitterConfig, // "chunks" only, splits documents into the parent chunks
+}
+
+impl Default for ParentConfig {
+    fn default() -> Self {
+        Self {
+            mode: ParentMode::None,
+            window: 1,
+            // Parents are only read by the answering model, so they aren't limited to the embedding model's input
+            splitter: SplitterConfig {
+                chunk_tokens: 1500,
+                tokenizer: Some("models/llama3-8b/tokenizer.json".to_string()),
+                ..SplitterConfig::with_strategy(SplitStrategy::Prompts)
+            },
+        }
+    }
+}
 
 // MODELS
-pub const EMBEDDING_MODEL_PATH: &str = "models/bge-large-en-v1.5-ft";
-pub const EMBEDDING_TOKENIZER: &str = "models/llama3-8b/tokenizer.json";
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct EmbeddingConfig {
+    pub model_path: String,
+    pub device: String, // "cuda:N" or "cpu", for `embed`, `pipeline` and the semantic splitter
+}
 
-pub const TRANSLATION_TOKENIZER: &str = "models/llama3-8b/tokenizer.json";
-pub const TRANSLATION_MODEL: &str = "models/llama3-8b/Meta-Llama-3-8B-Instruct.Q5_K_M.gguf";
+impl Default for EmbeddingConfig {
+    fn default() -> Self {
+        Self {
+            model_path: "models/bge-large-en-v1.5-ft".to_string(),
+            device: "cuda:0".to_string(),
+        }
+    }
+}
 
-pub const QUESTION_TOKENIZER: &str = "models/llama3-8b/tokenizer.json";
-pub const QUESTION_MODEL: &str = "models/llama3-8b/Meta-Llama-3-8B-Instruct.Q5_K_M.gguf";
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct QdrantConfig {
+    pub server: String,
+    pub collection: String,
+}
 
-pub const KEYWORD_DECORATOR_TOKENIZER: &str = "models/llama3-8b/tokenizer.json";
-pub const KEYWORD_DECORATOR_MODEL: &str = "models/llama3-8b/Meta-Llama-3-8B-Instruct.Q5_K_M.gguf";
+impl Default for QdrantConfig {
+    fn default() -> Self {
+        Self {
+            server: "http://localhost:6334".to_string(),
+            collection: "urska_md_baai_ft_decorated".to_string(),
+        }
+    }
+}
 
 // PROGRESS CONTROL
-pub const FILES_TO_PROCESS: Option<usize> = None; // limiter
-pub const PAR_CHUNK_SIZE: u64 = 2;
-
-
-// TRANSLATION MODEL SETTINGS
-pub const SEED: u64 = 42;
-pub const TEMPERATURE: f64 = 0.4;
-pub const SAMPLE_LEN: usize = 1000;
-pub const TOP_K: Option<usize> = None;
-pub const TOP_P: Option<f64> = None;
-pub const VERBOSE_PROMPT: bool = false;
-pub const SPLIT_PROPMT: bool = false;
-pub const REPEAT_PENALTY: f32 = 1.1;
-pub const REPEAT_LAST_N: usize = 64;
\ No newline at end of file
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ProgressConfig {
+    pub files_to_process: Option<usize>, // limiter
+    pub par_chunk_size: u64,
+    pub incremental: bool, // only process files that are new or changed since the last run
+}
+
+impl Default for ProgressConfig {
+    fn default() -> Self {
+        Self {
+            files_to_process: None,
+            par_chunk_size: 2,
+            incremental: true,
+        }
+    }
+}
+
+// MODEL SETTINGS
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct GenerationConfig {
+    pub seed: u64,
+    pub temperature: f64,
+    pub sample_len: usize,
+    pub top_k: Option<usize>,
+    pub top_p: Option<f64>,
+    pub verbose_prompt: bool,
+    pub split_prompt: bool,
+    pub repeat_penalty: f32,
+    pub repeat_last_n: usize,
+}
+
+impl Default for GenerationConfig {
+    fn default() -> Self {
+        Self {
+            seed: 42,
+            temperature: 0.4,
+            sample_len: 1000,
+            top_k: None,
+            top_p: None,
+            verbose_prompt: false,
+            split_prompt: false,
+            repeat_penalty: 1.1,
+            repeat_last_n: 64,
+        }
+    }
+}
+
+// LOADING
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct LoaderConfig {
+    pub recursive: bool,
+    pub follow_symlinks: bool,
+    pub include: Vec<String>, // globs relative to the input folder
+    pub exclude: Vec<String>,
+    pub encoding_tld: Option<String>, // hint for guessing the encoding of non UTF-8 files
+    pub detect_language: bool, // for documents without a language in their metadata
+    pub language_min_confidence: f64,
+    pub tables: Vec<TableConfig>, // CSV / spreadsheet settings, the first entry matching a file is used
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct TableConfig {
+    pub files: String, // glob relative to the input folder
+    pub template: Option<String>, // e.g. "Course {name} in programme {programme} has {ects} ECTS"
+    pub key_column: Option<String>, // column with a stable row ID, row numbers are used otherwise
+    pub delimiter: Option<char>, // CSV only, detected from the header if not set
+    pub sheet: Option<String>, // spreadsheets only, all sheets if not set
+}
+
+impl Default for LoaderConfig {
+    fn default() -> Self {
+        Self {
+            recursive: true,
+            follow_symlinks: false,
+            include: vec!["**/*.txt".to_string(), "**/*.md".to_string(), "**/*.pdf".to_string(), "**/*.html".to_string(), "**/*.htm".to_string(), "**/*.docx".to_string(), "**/*.odt".to_string(), "**/*.csv".to_string(), "**/*.xlsx".to_string()],
+            exclude: vec![],
+            encoding_tld: Some("si".to_string()),
+            detect_language: true,
+            language_min_confidence: 0.5,
+            tables: vec![],
+        }
+    }
+}
+
+// DEDUPLICATION
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DedupConfig {
+    pub shingle_size: usize, // words per shingle of the SimHash fingerprint
+    pub max_distance: u32, // differing fingerprint bits up to which chunks count as near duplicates
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        Self {
+            shingle_size: 3,
+            max_distance: 3,
+        }
+    }
+}
+
+// CHUNK FILTER
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct FilterConfig {
+    pub enabled: bool, // filter chunks before prompting in `decorate`, `embed`, `generate-questions` and `plan`
+    pub min_alpha_ratio: f64, // share of letters among non-whitespace characters
+    pub min_tokens: usize,
+    pub deny_patterns: Vec<String>, // regexes, chunks matching any of them are dropped
+    pub detect_toc: bool, // drop tables of contents
+    pub tokenizer: Option<String>, // used to count tokens, the embedding model's tokenizer if not set
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_alpha_ratio: 0.2,
+            min_tokens: 5,
+            deny_patterns: vec![],
+            detect_toc: true,
+            tokenizer: None,
+        }
+    }
+}
+
+// PLANNING
+// Throughput numbers can be taken from a run with `generation.verbose_prompt = true`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct PlanConfig {
+    pub prompt_tokens_per_sec: f64,
+    pub generated_tokens_per_sec: f64,
+    pub generated_tokens_per_prompt: usize,
+}
+
+impl Default for PlanConfig {
+    fn default() -> Self {
+        Self {
+            prompt_tokens_per_sec: 800.,
+            generated_tokens_per_sec: 30.,
+            generated_tokens_per_prompt: 400,
+        }
+    }
+}
+
+impl Config {
+    /// Builds the runtime configuration from a TOML file, environment variables and CLI overrides.
+    ///
+    /// Values are layered in the following order, later layers winning:
+    /// built-in defaults, the config file, `DOC_PREP__<SECTION>__<KEY>` environment variables
+    /// and finally `section.key=value` overrides passed on the command line.
+    ///
+    /// # Parameters
+    /// - `config_file`: Path to the TOML file. If `None`, `./config.toml` is used when it exists.
+    /// - `overrides`: List of `section.key=value` strings.
+    ///
+    /// # Returns
+    /// Returns a `Result` containing the assembled `Config`.
+    ///
+    /// # Errors
+    /// - Returns an error if an explicitly given config file can't be read, if the TOML is invalid
+    ///   or if an override is malformed or has the wrong type for its key.
+    pub fn load(config_file: Option<&str>, overrides: &[String]) -> Result<Self> {
+        let mut table = match config_file {
+            Some(file) => read_config_table(file)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => read_config_table(DEFAULT_CONFIG_FILE)?,
+            None => toml::Table::new(),
+        };
+
+        for (key, value) in std::env::vars() {
+            if let Some(key) = key.strip_prefix(ENV_PREFIX) {
+                let key = key.to_lowercase().replace("__", ".");
+                set_key(&mut table, &key, &value)?;
+            }
+        }
+
+        for entry in overrides {
+            let (key, value) = match entry.split_once('=') {
+                Some(kv) => kv,
+                None => return Err(Error::msg(format!("Invalid override '{}', expected KEY=VALUE", entry))),
+            };
+            set_key(&mut table, key.trim(), value.trim())?;
+        }
+
+        Ok(toml::Value::Table(table).try_into()?)
+    }
+}
+
+/// Installs the configuration used by the rest of the application. Only the first call has an effect.
+pub fn init_config(config: Config) {
+    if CONFIG.set(config).is_err() {
+        println!("Configuration already initialized. Ignoring new configuration.");
+    }
+}
+
+/// Returns the active configuration, falling back to the defaults if none was installed.
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+fn read_config_table(file: &str) -> Result<toml::Table> {
+    let contents = fs::read_to_string(file)
+        .map_err(|e| Error::msg(format!("Can't read config file {}: {}", file, e)))?;
+    Ok(toml::from_str(&contents)?)
+}
+
+fn set_key(table: &mut toml::Table, key: &str, raw_value: &str) -> Result<()> {
+    insert_key(table, key, parse_value(key, raw_value))
+}
+
+fn insert_key(table: &mut toml::Table, key: &str, value: toml::Value) -> Result<()> {
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = match parts.pop() {
+        Some(l) if !l.is_empty() => l,
+        _ => return Err(Error::msg(format!("Invalid config key '{}'", key))),
+    };
+
+    let mut current = table;
+    for part in parts {
+        let entry = current
+            .entry(part.to_string())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        current = match entry.as_table_mut() {
+            Some(t) => t,
+            None => return Err(Error::msg(format!("Config key '{}' is not a section", part))),
+        };
+    }
+    current.insert(last.to_string(), value);
+    Ok(())
+}
+
+fn parse_value(key: &str, raw_value: &str) -> toml::Value {
+    let as_string = || toml::Value::String(raw_value.to_string());
+    // Anything that is not a valid TOML value (bare words, paths,...) is treated as a string.
+    let value = match toml::from_str::<toml::Table>(&format!("value = {}", raw_value)) {
+        Ok(mut t) => t.remove("value").unwrap_or_else(as_string),
+        Err(_) => return as_string(),
+    };
+    // So is a value of the wrong type for its key, e.g. `qdrant.collection=2024`
+    if !value.is_str() && !fits_key(key, value.clone()) && fits_key(key, as_string()) {
+        return as_string();
+    }
+    value
+}
+
+/// Whether `value` deserializes at `key` on its own, with defaults for everything else.
+fn fits_key(key: &str, value: toml::Value) -> bool {
+    let mut table = toml::Table::new();
+    insert_key(&mut table, key, value).is_ok() && toml::Value::Table(table).try_into::<Config>().is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_take_the_type_of_their_key() {
+        let overrides = vec!["qdrant.collection=2024".to_string(), "progress.files_to_process=5".to_string()];
+        let config = Config::load(None, &overrides).unwrap();
+        assert_eq!(config.qdrant.collection, "2024");
+        assert_eq!(config.progress.files_to_process, Some(5));
+    }
+
+    #[test]
+    fn wrong_types_are_still_rejected() {
+        assert!(Config::load(None, &["progress.files_to_process=many".to_string()]).is_err());
+    }
+}
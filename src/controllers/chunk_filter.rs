@@ -0,0 +1,249 @@
+use std::fmt;
+
+use anyhow::{Error, Result};
+use regex::Regex;
+use tokenizers::Tokenizer;
+
+use crate::{config::FilterConfig, docs::chunk::Chunk, llm::{loader::load_embedding_tokenizer, tokenizer::load_tokenizer}};
+
+/// Headings that open a table of contents
+const TOC_HEADINGS: [&str; 5] = ["kazalo", "vsebina", "table of contents", "contents", "toc"];
+/// Share of lines ending in a page number from which a chunk counts as a table of contents
+const TOC_LINE_RATIO: f64 = 0.5;
+const TOC_MIN_LINES: usize = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DropReason {
+    LowAlphaRatio(f64),
+    TooFewTokens(usize),
+    Denied(String),
+    TableOfContents,
+}
+
+impl fmt::Display for DropReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DropReason::LowAlphaRatio(ratio) => write!(f, "only {:.0}% letters", ratio * 100.),
+            DropReason::TooFewTokens(tokens) => write!(f, "only {} tokens", tokens),
+            DropReason::Denied(pattern) => write!(f, "matches deny pattern '{}'", pattern),
+            DropReason::TableOfContents => write!(f, "table of contents"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FilterReport {
+    pub low_alpha: usize,
+    pub too_few_tokens: usize,
+    pub denied: usize,
+    pub toc: usize,
+}
+
+impl FilterReport {
+    pub fn add(&mut self, reason: &DropReason) {
+        match reason {
+            DropReason::LowAlphaRatio(_) => self.low_alpha += 1,
+            DropReason::TooFewTokens(_) => self.too_few_tokens += 1,
+            DropReason::Denied(_) => self.denied += 1,
+            DropReason::TableOfContents => self.toc += 1,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.low_alpha + self.too_few_tokens + self.denied + self.toc
+    }
+}
+
+/// Drops chunks not worth prompting or embedding: page numbers, empty table skeletons,
+/// tables of contents, signature blocks and whatever else `filter.deny_patterns` matches.
+pub struct ChunkFilter {
+    settings: FilterConfig,
+    deny: Vec<Regex>,
+    toc_line: Regex,
+    tokenizer: Tokenizer,
+}
+
+impl ChunkFilter {
+    pub fn load(settings: &FilterConfig) -> Result<Self> {
+        let tokenizer = match &settings.tokenizer {
+            Some(path) => load_tokenizer(path)?,
+            None => load_embedding_tokenizer()?,
+        };
+        Self::with_tokenizer(settings, tokenizer)
+    }
+
+    pub(crate) fn with_tokenizer(settings: &FilterConfig, tokenizer: Tokenizer) -> Result<Self> {
+        let deny = settings.deny_patterns
+            .iter()
+            .map(|p| Regex::new(p).map_err(|e| Error::msg(format!("Invalid deny pattern '{}': {}", p, e))))
+            .collect::<Result<Vec<Regex>>>()?;
+        Ok(Self {
+            settings: settings.clone(),
+            deny,
+            // Text, then a page number after dot leaders, spaces or a table cell border
+            toc_line: Regex::new(r"\S.*?(\.{2,}|…+|\s|\|)\s*\d{1,4}\s*\|?\s*$")?,
+            tokenizer,
+        })
+    }
+
+    /// Returns why the chunk should be dropped, or `None` if it passes every rule.
+    ///
+    /// # Errors
+    /// - Returns an error if the chunk can't be tokenized.
+    pub fn check(&self, text: &str) -> Result<Option<DropReason>> {
+        let ratio = alpha_ratio(text);
+        if ratio < self.settings.min_alpha_ratio {
+            return Ok(Some(DropReason::LowAlphaRatio(ratio)));
+        }
+        if let Some(pattern) = self.deny.iter().find(|p| p.is_match(text)) {
+            return Ok(Some(DropReason::Denied(pattern.as_str().to_string())));
+        }
+        if self.settings.detect_toc && self.is_toc(text) {
+            return Ok(Some(DropReason::TableOfContents));
+        }
+        let tokens = self.tokenizer.encode(text, false).map_err(Error::msg)?.len();
+        if tokens < self.settings.min_tokens {
+            return Ok(Some(DropReason::TooFewTokens(tokens)));
+        }
+        Ok(None)
+    }
+
+    /// Like `check`, but logs the dropped chunk with the reason.
+    pub fn keep(&self, text: &str, source: &str, index: usize) -> bool {
+        self.drop_reason(text, source, index).is_none()
+    }
+
+    pub fn filter_chunks(&self, chunks: Vec<Chunk>) -> (Vec<Chunk>, FilterReport) {
+        let mut report = FilterReport::default();
+        let kept = chunks
+            .into_iter()
+            .filter(|chunk| match self.drop_reason(&chunk.text, &chunk.relative_path, chunk.index) {
+                Some(reason) => {
+                    report.add(&reason);
+                    false
+                },
+                None => true,
+            })
+            .collect();
+        (kept, report)
+    }
+
+    /// Checks the chunk and logs why it is dropped. Chunks that can't be tokenized are
+    /// kept, as the token count is the only rule left for them, and logged as well.
+    fn drop_reason(&self, text: &str, source: &str, index: usize) -> Option<DropReason> {
+        match self.check(text) {
+            Ok(Some(reason)) => {
+                println!("Dropped chunk {} of {}: {}", index, source, reason);
+                Some(reason)
+            },
+            Ok(None) => None,
+            Err(e) => {
+                println!("Can't count the tokens of chunk {} of {}, keeping it: {:#?}", index, source, e);
+                None
+            },
+        }
+    }
+
+    /// Most lines end in a page number, after dot leaders or below a contents heading.
+    fn is_toc(&self, text: &str) -> bool {
+        let lines: Vec<&str> = text
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !is_delimiter_line(l))
+            .collect();
+        let has_heading = lines.first().is_some_and(|l| {
+            let heading = l.trim_start_matches('#').trim_matches(|c: char| matches!(c, '*' | ':' | '|') || c.is_whitespace()).to_lowercase();
+            TOC_HEADINGS.contains(&heading.as_str())
+        });
+        let entries = lines
+            .iter()
+            .filter(|l| self.toc_line.is_match(l))
+            // Without a heading only dot leaders tell entries apart from table rows ending in numbers
+            .filter(|l| has_heading || l.contains("..") || l.contains('…'))
+            .count();
+        entries >= TOC_MIN_LINES && entries as f64 >= lines.len() as f64 * TOC_LINE_RATIO
+    }
+}
+
+/// Share of letters among the non-whitespace characters.
+fn alpha_ratio(text: &str) -> f64 {
+    let (letters, total) = text
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .fold((0, 0), |(letters, total), c| (letters + c.is_alphabetic() as usize, total + 1));
+    match total {
+        0 => 0.,
+        _ => letters as f64 / total as f64,
+    }
+}
+
+/// Markdown table delimiter rows and horizontal rules.
+fn is_delimiter_line(line: &str) -> bool {
+    line.chars().all(|c| matches!(c, '|' | '-' | ':' | '=' | '*' | '_' | ' '))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::controllers::splitter::tests::word_tokenizer;
+
+    use super::*;
+
+    fn filter(settings: FilterConfig) -> ChunkFilter {
+        ChunkFilter::with_tokenizer(&settings, word_tokenizer()).unwrap()
+    }
+
+    #[test]
+    fn drops_page_numbers_and_table_skeletons() {
+        let filter = filter(FilterConfig::default());
+        assert!(matches!(filter.check("- 12 -").unwrap(), Some(DropReason::LowAlphaRatio(_))));
+        assert!(matches!(filter.check("| | |\n|---|---|\n| 1 | 2 |").unwrap(), Some(DropReason::LowAlphaRatio(_))));
+        assert_eq!(filter.check("Vpis poteka v treh prijavnih rokih.").unwrap(), None);
+    }
+
+    #[test]
+    fn drops_short_chunks() {
+        let filter = filter(FilterConfig::default());
+        assert_eq!(filter.check("Kazalo slik").unwrap(), Some(DropReason::TooFewTokens(2)));
+    }
+
+    #[test]
+    fn drops_denied_chunks() {
+        let filter = filter(FilterConfig {
+            deny_patterns: vec![r"(?i)^podpis\b".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(
+            filter.check("Podpis dekana in žig fakultete ter datum").unwrap(),
+            Some(DropReason::Denied(r"(?i)^podpis\b".to_string()))
+        );
+        assert!(ChunkFilter::with_tokenizer(&FilterConfig { deny_patterns: vec!["(".to_string()], ..Default::default() }, word_tokenizer()).is_err());
+    }
+
+    #[test]
+    fn detects_tables_of_contents() {
+        let filter = filter(FilterConfig::default());
+        let dotted = "Uvod ........ 3\nVpis ........ 5\nŠtipendije ........ 9\nŠtudentski dom ........ 12";
+        assert_eq!(filter.check(dotted).unwrap(), Some(DropReason::TableOfContents));
+        let headed = "## Kazalo\n\nUvod 3\nVpis 5\nŠtipendije 9";
+        assert_eq!(filter.check(headed).unwrap(), Some(DropReason::TableOfContents));
+        // Rows ending in numbers without dot leaders or a heading are a table, not contents
+        let table = "| Predmet | ECTS |\n|---|---|\n| Matematika | 6 |\n| Fizika | 6 |\n| Kemija | 5 |";
+        assert_eq!(filter.check(table).unwrap(), None);
+        assert_eq!(self::filter(FilterConfig { detect_toc: false, ..Default::default() }).check(dotted).unwrap(), None);
+    }
+
+    #[test]
+    fn reports_count_each_reason() {
+        let mut report = FilterReport::default();
+        report.add(&DropReason::TableOfContents);
+        report.add(&DropReason::TooFewTokens(1));
+        report.add(&DropReason::TooFewTokens(2));
+        assert_eq!((report.toc, report.too_few_tokens, report.total()), (1, 2, 3));
+    }
+
+    #[test]
+    fn alpha_ratio_ignores_whitespace() {
+        assert_eq!(alpha_ratio("ab 12"), 0.5);
+        assert_eq!(alpha_ratio(" \n"), 0.);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::docs::{doc::Doc, pdf_loader::join_pages};
+
+pub fn clean_docs(docs: Vec<Doc>) -> Vec<Doc> {
+    docs.into_iter()
+        .map(clean_doc)
+        .collect()
+}
+
+fn clean_doc(doc: Doc) -> Doc {
+    if doc.pages.is_empty() {
+        return Doc {
+            content: clean_content(&doc.content),
+            ..doc
+        };
+    }
+
+    // Clean page by page so the page spans stay valid
+    let last_page = doc.pages.iter().map(|s| s.page).max().unwrap_or(0);
+    let mut pages = vec![String::new(); last_page];
+    for span in &doc.pages {
+        let page = &mut pages[span.page - 1];
+        if !page.is_empty() {
+            page.push_str("\n\n");
+        }
+        page.push_str(&clean_content(&doc.content[span.start..span.end]));
+    }
+    let (content, spans) = join_pages(pages);
+    Doc {
+        content,
+        pages: spans,
+        ..doc
+    }
+}
+
+/// Trims trailing whitespace and collapses runs of empty lines, so the
+/// `"\n\n"` paragraph boundaries the splitters rely on stay consistent.
+pub fn clean_content(content: &str) -> String {
+    let mut cleaned = String::with_capacity(content.len());
+    let mut empty_lines = 0;
+
+    for line in content.replace("\r\n", "\n").lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            empty_lines += 1;
+            continue;
+        }
+        if !cleaned.is_empty() {
+            cleaned.push_str(if empty_lines > 0 { "\n\n" } else { "\n" });
+        }
+        cleaned.push_str(line);
+        empty_lines = 0;
+    }
+
+    cleaned
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::docs::doc::{DocMetadata, PageSpan};
+    use super::*;
+
+    #[test]
+    fn empty_lines_collapse_to_paragraph_breaks() {
+        assert_eq!(clean_content("Naslov  \r\n\r\n\r\n\nPrvi odstavek\t\nse nadaljuje\n\n"), "Naslov\n\nPrvi odstavek\nse nadaljuje");
+    }
+
+    #[test]
+    fn page_spans_follow_the_cleaned_content() {
+        let (content, pages) = join_pages(vec!["Prva  \n\n\n\nstran".to_string(), "Druga   \nstran".to_string()]);
+        let doc = Doc { id: "1".to_string(), content, file_name: "a.pdf".to_string(), relative_path: "a.pdf".to_string(), pages, metadata: DocMetadata::default() };
+        let cleaned = clean_doc(doc);
+
+        assert_eq!(cleaned.content, "Prva\n\nstran\n\nDruga\nstran");
+        assert_eq!(cleaned.pages, vec![
+            PageSpan { page: 1, start: 0, end: 11 },
+            PageSpan { page: 2, start: 13, end: 24 },
+        ]);
+    }
+}
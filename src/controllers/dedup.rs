@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+
+use crate::{config::config, docs::{chunk::Chunk, manifest::hash_bytes}};
+
+const FINGERPRINT_BITS: u32 = 64;
+
+#[derive(Debug, Default)]
+pub struct DedupReport {
+    pub exact: usize,
+    pub near: usize,
+}
+
+/// Removes exact duplicates (same normalized text) and near-duplicates (SimHash
+/// fingerprints within `dedup.max_distance` bits) from the chunks.
+///
+/// The first chunk of every group of duplicates is kept as the canonical one and the
+/// sources of the others are recorded in its `alternate_sources`. Only the pipeline's
+/// `dedup` stage calls this, `embed` and `decorate` don't deduplicate.
+pub fn dedup_chunks(chunks: Vec<Chunk>) -> (Vec<Chunk>, DedupReport) {
+    let settings = &config().dedup;
+    let normalized: Vec<String> = chunks.iter().map(|c| normalize(&c.text)).collect();
+    let mut groups = DisjointSet::new(chunks.len());
+    let mut report = DedupReport::default();
+
+    let mut by_hash: HashMap<String, usize> = HashMap::new();
+    for (index, text) in normalized.iter().enumerate() {
+        let hash = hash_bytes(text.as_bytes());
+        match by_hash.get(&hash) {
+            Some(first) => {
+                groups.union(*first, index);
+                report.exact += 1;
+            },
+            None => {
+                by_hash.insert(hash, index);
+            },
+        }
+    }
+
+    // Fingerprints within `max_distance` bits share at least one of `max_distance + 1`
+    // bands, so only chunks sharing a band have to be compared.
+    let fingerprints: Vec<Option<u64>> = normalized.iter().map(|t| simhash(t, settings.shingle_size)).collect();
+    let bands = (settings.max_distance + 1).min(FINGERPRINT_BITS);
+    let mut buckets: HashMap<(u32, u64), Vec<usize>> = HashMap::new();
+    for (index, fingerprint) in fingerprints.iter().enumerate() {
+        let fingerprint = match fingerprint {
+            Some(f) if groups.find(index) == index => *f,
+            _ => continue, // exact duplicate or no words to compare
+        };
+        for band in 0..bands {
+            let key = (band, band_value(fingerprint, band, bands));
+            let bucket = buckets.entry(key).or_default();
+            for &other in bucket.iter() {
+                let distance = fingerprints[other].map_or(u32::MAX, |f| (f ^ fingerprint).count_ones());
+                if distance <= settings.max_distance && groups.find(other) != groups.find(index) {
+                    groups.union(other, index);
+                    report.near += 1;
+                }
+            }
+            bucket.push(index);
+        }
+    }
+
+    let mut alternates: HashMap<usize, Vec<String>> = HashMap::new();
+    for (index, chunk) in chunks.iter().enumerate() {
+        let canonical = groups.find(index);
+        if canonical != index && chunk.relative_path != chunks[canonical].relative_path {
+            let sources = alternates.entry(canonical).or_default();
+            if !sources.contains(&chunk.relative_path) {
+                sources.push(chunk.relative_path.clone());
+            }
+        }
+    }
+
+    let kept = chunks
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| groups.find(*index) == *index)
+        .map(|(index, mut chunk)| {
+            if let Some(sources) = alternates.remove(&index) {
+                chunk.alternate_sources.extend(sources);
+            }
+            chunk
+        })
+        .collect();
+
+    (kept, report)
+}
+
+fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .map(|w| w.to_lowercase())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn simhash(text: &str, shingle_size: usize) -> Option<u64> {
+    let words: Vec<&str> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+    if words.is_empty() {
+        return None;
+    }
+    let shingle_size = shingle_size.clamp(1, words.len());
+
+    let mut weights = [0i64; FINGERPRINT_BITS as usize];
+    for shingle in words.windows(shingle_size) {
+        let hash = fnv1a(shingle.join(" ").as_bytes());
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            *weight += if hash >> bit & 1 == 1 { 1 } else { -1 };
+        }
+    }
+
+    let fingerprint = weights
+        .iter()
+        .enumerate()
+        .filter(|(_, w)| **w > 0)
+        .fold(0, |fingerprint, (bit, _)| fingerprint | 1 << bit);
+    Some(fingerprint)
+}
+
+fn band_value(fingerprint: u64, band: u32, bands: u32) -> u64 {
+    let width = FINGERPRINT_BITS / bands;
+    let start = band * width;
+    // The last band takes the remaining bits
+    let end = if band + 1 == bands { FINGERPRINT_BITS } else { start + width };
+    let mask = if end - start == FINGERPRINT_BITS { u64::MAX } else { (1 << (end - start)) - 1 };
+    fingerprint >> start & mask
+}
+
+// Stable across runs and platforms, unlike `DefaultHasher`
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Union-find where the root of every set is its smallest index, so the
+/// first chunk of a group of duplicates stays canonical.
+struct DisjointSet {
+    parents: Vec<usize>,
+}
+
+impl DisjointSet {
+    fn new(size: usize) -> Self {
+        Self { parents: (0..size).collect() }
+    }
+
+    fn find(&mut self, index: usize) -> usize {
+        let mut root = index;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+        let mut current = index;
+        while self.parents[current] != root {
+            let next = self.parents[current];
+            self.parents[current] = root;
+            current = next;
+        }
+        root
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parents[a.max(b)] = a.min(b);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(relative_path: &str, text: &str) -> Chunk {
+        Chunk {
+            doc_id: relative_path.to_string(),
+            index: 0,
+            file_name: relative_path.to_string(),
+            relative_path: relative_path.to_string(),
+            text: text.to_string(),
+            headings: vec![],
+            span: Default::default(),
+            strategy: Default::default(),
+            keywords: None,
+            pages: vec![],
+            metadata: Default::default(),
+            alternate_sources: vec![],
+        }
+    }
+
+    const PASSAGE: &str = "Vpis v prvi letnik poteka v treh prijavnih rokih, ki jih objavi univerza v razpisu za vpis v študijske programe.";
+
+    #[test]
+    fn exact_duplicates_ignore_case_and_whitespace() {
+        let chunks = vec![
+            chunk("a.md", PASSAGE),
+            chunk("b.md", &PASSAGE.to_uppercase().replace(' ', "  \n")),
+            chunk("c.md", "Štipendije podeljuje sklad za razvoj kadrov."),
+        ];
+        let (kept, report) = dedup_chunks(chunks);
+        assert_eq!((report.exact, report.near), (1, 0));
+        assert_eq!(kept.len(), 2);
+        assert_eq!(kept[0].relative_path, "a.md");
+        assert_eq!(kept[0].alternate_sources, vec!["b.md".to_string()]);
+    }
+
+    #[test]
+    fn near_duplicates_are_merged_into_the_first() {
+        // Same words with different markup, e.g. from a converted copy of the document
+        let changed = format!("**{}**", PASSAGE.replace(',', ""));
+
+        let (kept, report) = dedup_chunks(vec![chunk("a.md", PASSAGE), chunk("b.md", &changed)]);
+        assert_eq!((report.exact, report.near), (0, 1));
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].alternate_sources, vec!["b.md".to_string()]);
+    }
+
+    #[test]
+    fn different_passages_are_kept() {
+        let chunks = vec![
+            chunk("a.md", PASSAGE),
+            chunk("b.md", "Študentski dom ima dvoposteljne sobe s skupno kuhinjo in pralnico v kleti."),
+            chunk("c.md", "123 — 456"),
+            chunk("d.md", "— —"),
+        ];
+        let (kept, report) = dedup_chunks(chunks);
+        assert_eq!((report.exact, report.near), (0, 0));
+        assert_eq!(kept.len(), 4);
+    }
+
+    #[test]
+    fn fingerprints_need_words() {
+        assert_eq!(simhash("— … —", 3), None);
+        assert!(simhash("ena", 3).is_some());
+    }
+
+    #[test]
+    fn bands_cover_every_bit() {
+        let fingerprint = u64::MAX;
+        let bits: u32 = (0..4).map(|band| band_value(fingerprint, band, 4).count_ones()).sum();
+        assert_eq!(bits, FINGERPRINT_BITS);
+        assert_eq!(band_value(fingerprint, 0, 1), u64::MAX);
+        let bits: u32 = (0..3).map(|band| band_value(fingerprint, band, 3).count_ones()).sum();
+        assert_eq!(bits, FINGERPRINT_BITS);
+    }
+
+    #[test]
+    fn sets_keep_their_smallest_index() {
+        let mut set = DisjointSet::new(5);
+        set.union(4, 2);
+        set.union(3, 4);
+        set.union(1, 0);
+        assert_eq!(set.find(3), 2);
+        assert_eq!(set.find(1), 0);
+        set.union(3, 1);
+        assert_eq!((0..5).map(|i| set.find(i)).collect::<Vec<_>>(), vec![0, 0, 0, 0, 0]);
+    }
+}
@@ -1,19 +1,17 @@
 use std::{cmp::min, sync::{Arc, Mutex}};
 use candle_core::Device;
 use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
-use tokio::runtime::Runtime;
 use crate::{
-    config::{KEYWORD_DECORATOR_MODEL, KEYWORD_DECORATOR_PROGRESS_FILE, KEYWORD_DECORATOR_SYSTEM_MSG, KEYWORD_DECORATOR_TOKENIZER, PAR_CHUNK_SIZE}, 
-    docs::{doc::Doc, embedded_doc::{EmbeddedDoc, Passage}, qdant::insert_docs, saver::{save_raw, save_to_json}}, 
-    llm::{embedding_model::embedd, model::load_model, prompt::{prompt_model, Prompt}, tokenizer::load_tokenizer}, 
+    config::config,
+    docs::{chunk::ProcessedDocumentChunk, loader::DocStream, saver::save_to_json},
+    llm::{model::load_model, prompt::{prompt_model, Prompt}, tokenizer::load_tokenizer},
     util::{get_progress_bar, load_progress, save_progress, Progress}
 };
-use super::splitter::split_partial_overlapping;
+use super::{chunk_filter::ChunkFilter, splitter::{build_splitter, with_breadcrumb}};
 
-pub type ProcessedDocumentChunk = (String, String, bool);
-
-pub fn decorate_passages(mut passages: Vec<Doc>) {
-    println!("Passages to decorate: {}", passages.len());
+pub fn generate_questions(passages: DocStream) {
+    let settings = &config().questions;
+    println!("Passages to generate questions for: {}", passages.remaining_files());
     let device1 = match Device::new_cuda(0) {
         Ok(cuda) => cuda,
         Err(e) => {
@@ -30,131 +28,118 @@ pub fn decorate_passages(mut passages: Vec<Doc>) {
         },
     };
 
-    let tokenizer = match load_tokenizer(KEYWORD_DECORATOR_TOKENIZER) {
+    let tokenizer = match load_tokenizer(&settings.tokenizer) {
         Ok(t) => t,
         Err(e) => panic!("Can't load tokenizer: {:#?}", e),
     };
 
-    let model1 = match load_model(KEYWORD_DECORATOR_MODEL, &device1) { 
+    let splitter = match build_splitter(&settings.splitter) {
+        Ok(s) => s,
+        Err(e) => panic!("Can't build splitter: {:#?}", e),
+    };
+
+    let filter = match config().filter.enabled {
+        true => match ChunkFilter::load(&config().filter) {
+            Ok(f) => Some(f),
+            Err(e) => panic!("Can't load chunk filter: {:#?}", e),
+        },
+        false => None,
+    };
+
+    let model1 = match load_model(&settings.model, &device1) {
         Ok(m) => Arc::new(Mutex::new(m)),
         Err(e) => panic!("Can't load model: {:#?}", e),
     };
 
-    let model2 = match load_model(KEYWORD_DECORATOR_MODEL, &device2) { 
+    let model2 = match load_model(&settings.model, &device2) {
         Ok(m) => Arc::new(Mutex::new(m)),
         Err(e) => panic!("Can't load model: {:#?}", e),
     };
 
-    let mut progress: Progress = load_progress(KEYWORD_DECORATOR_PROGRESS_FILE);
+    let mut progress: Progress = load_progress(&settings.progress_file);
+
 
-    
     let to_process = if let Some(bound) = progress.files_to_process {
-        min(bound, passages.len())
+        min(bound, passages.remaining_files())
     } else {
-        passages.len()
+        passages.remaining_files()
     };
 
     let mut done = progress.batches_done * progress.par_chunk_size;
     let progress_bar = get_progress_bar(to_process, 0);
-    progress_bar.inc(done); 
-    
-    passages.drain(0..(done as usize));
+    progress_bar.inc(done);
+
+    let mut passages = passages.skip_docs(done as usize);
 
-    for batch in passages.chunks(progress.par_chunk_size as usize) {
+    loop {
+        let batch = passages.next_batch(progress.par_chunk_size as usize);
+        if batch.is_empty() {
+            break;
+        }
 
-        let decorated_docs: Vec<Vec<EmbeddedDoc>> = batch.par_iter().enumerate().map(|(index, document)| {
+        let results: Vec<Vec<ProcessedDocumentChunk>> = batch.par_iter().enumerate().map(|(index, document)| {
 
             // Select the appropriate model and device based on the index
             let (mut model, device) = match index % 2 {
                 0 => (model1.lock().unwrap(), &device1),
-                _ => (model2.lock().unwrap(), &device2),  
+                _ => (model2.lock().unwrap(), &device2),
             };
 
-            let mut responses: Vec<ProcessedDocumentChunk> = vec![]; 
-            let prompts = split_partial_overlapping(document);
-            let prompts_len = prompts.len();
-            let doc_progress = get_progress_bar(prompts_len, 1);
-            
-            for prompt_string in prompts {
+            let mut responses: Vec<ProcessedDocumentChunk> = vec![];
+            let chunks: Vec<_> = splitter
+                .split_located(document)
+                .into_iter()
+                .filter(|(text, provenance)| filter.as_ref().is_none_or(|f| f.keep(text, &provenance.source, provenance.index)))
+                .collect();
+            let doc_progress = get_progress_bar(chunks.len(), 1);
+
+            for (text, provenance) in chunks {
                 // Process the prompt with the selected model and device
-                let question = prompt_string.clone();
-                let prompt = Prompt::One(
-                    KEYWORD_DECORATOR_SYSTEM_MSG.to_string(),
-                    format!(
-                        "Name of the file: {}\nPassage: {}\n\n Response template: 'KW: <kw1>, <kw2>, <kw3>,...'", 
-                        document.file_name, 
-                        prompt_string
-                    )
-                );
-                match prompt_model(&mut *model, &tokenizer, prompt, device) {
-                    Ok(out) => responses.push((question, out, true)),
-                    Err(e) => responses.push((question, e.to_string(), false)),
+                let passage = with_breadcrumb(&provenance.headings, &text);
+                let prompt = question_prompt(&document.file_name, &passage);
+                let (output, success) = match prompt_model(&mut *model, &tokenizer, prompt, device) {
+                    Ok(out) => (out, true),
+                    Err(e) => (e.to_string(), false),
                 };
+                responses.push(ProcessedDocumentChunk { input: passage, output, success, provenance });
                 doc_progress.inc(1);
             }
 
-            let mut embedded_docs = vec![];
-
-            let rt = Runtime::new().unwrap();  // Create a new Tokio runtime
-            for (passage, keywords, success) in responses {
-                if success {
-                    let content = format!("{}\n\n{}", keywords, passage);
-                    let embedding_vector = match rt.block_on(async { embedd(&content).await }) {
-                        Ok(vec) => vec.to_vec2::<f32>(),
-                        Err(e) => {
-                            println!("Ccant embedd passage: {:#?}\n{}",e, passage);
-                            continue;
-                        },
-                    };
-
-                    let embedding_vector = match embedding_vector {
-                        Ok(v) => v,
-                        Err(e) =>  {
-                            println!("Cant convert passage embedding tensor: {:#?}\n{}",e, passage);
-                            continue;
-                        },
-                    };
-
-                    let vector = embedding_vector.get(0).unwrap().clone();
-
-                    embedded_docs.push(EmbeddedDoc {
-                        vector,
-                        content: Passage {
-                            usage: 0,
-                            text: content,
-                        }
-                    });
-                }
-            }
-
-            match rt.block_on(async { insert_docs(embedded_docs.clone()).await }) {
-                Ok(_) => (),
-                Err(e) => {
-                    println!("Error upserting to Qdrant: {:#?}", e);
-                    ()
-                },
-            }
-
-            embedded_docs
+            responses
         }).collect();
 
-        
-
-        progress_bar.inc(PAR_CHUNK_SIZE); 
+        progress_bar.inc(progress.par_chunk_size);
         done += progress.par_chunk_size;
 
         progress.batches_done += 1;
 
+        for records in &results {
+            if let Err(e) = save_to_json(records, &settings.output_file) {
+                println!("Failed saving records: {:#?}", e)
+            };
+        }
 
-        if let Err(e) = save_progress(&progress, KEYWORD_DECORATOR_PROGRESS_FILE) {
+        if let Err(e) = save_progress(&progress, &settings.progress_file) {
             println!("Failed to save progress file: {:#?}", e);
         }
 
-        if done >= to_process as u64 {
+        // Only an explicit bound stops early, as spreadsheets yield a document per row
+        if progress.files_to_process.is_some_and(|bound| done >= bound as u64) {
             break;
         }
     }
 
-    progress_bar.finish_with_message("Deorating passages complete!");
+    progress_bar.finish_with_message("Generating questions complete!");
+    passages.report().print();
 }
 
+pub fn question_prompt(file_name: &str, passage: &str) -> Prompt {
+    Prompt::One(
+        config().questions.system_msg.clone(),
+        format!(
+            "Name of the file: {}\nPassage: {}",
+            file_name,
+            passage
+        )
+    )
+}
@@ -1,19 +1,41 @@
-use std::{cmp::min, sync::{Arc, Mutex}};
+use std::{cmp::min, collections::{HashMap, HashSet}, sync::{Arc, Mutex}};
 use candle_core::Device;
 use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use tokio::runtime::Runtime;
 use crate::{
-    config::{EMBEDD, KEYWORD_DECORATOR_MODEL, KEYWORD_DECORATOR_PROGRESS_FILE, KEYWORD_DECORATOR_SYSTEM_MSG, KEYWORD_DECORATOR_TOKENIZER, PAR_CHUNK_SIZE}, 
-    docs::{doc::Doc, embedded_doc::{EmbeddedDoc, Passage}, qdant::insert_docs, saver::{save_raw, save_to_json}}, 
-    llm::{embedding_model::embedd, model::load_model, prompt::{prompt_model, Prompt}, tokenizer::load_tokenizer}, 
+    config::config, 
+    docs::{chunk::{ProcessedDocumentChunk, Provenance}, doc::chunk_id, embedded_doc::{EmbeddedDoc, Passage}, loader::DocStream, manifest::{hash_bytes, prepare_incremental, Manifest, ManifestOutput}, qdant::{delete_points, insert_docs}, saver::{json_line, save_raw, save_to_json}}, 
+    llm::{embedding_model::embedd_with, loader::{embedding_gpu, load_bert_model, load_embedding_max_tokens}, model::load_model, prompt::{prompt_model, Prompt}, tokenizer::load_tokenizer}, 
     util::{get_progress_bar, load_progress, save_progress, Progress}
 };
-use super::splitter::{merge_parsed_documents, split_to_prompts};
+use super::chunk_filter::ChunkFilter;
+use super::splitter::{build_splitter, merge_parsed_documents, parents::Parents, with_breadcrumb, EmbeddingWindow};
 
-pub type ProcessedDocumentChunk = (String, String, bool);
+/// Relative path, responses, outputs (`None` if they weren't stored) and whether every chunk succeeded
+type DecoratedDoc = (String, Vec<ProcessedDocumentChunk>, Option<Vec<ManifestOutput>>, bool);
 
-pub fn decorate_passages(mut passages: Vec<Doc>) -> Vec<ProcessedDocumentChunk> {
-    println!("Passages to decorate: {}", passages.len());
+/// Generates keywords for the passages of each document. Without `embed` the results are
+/// appended to `decorate.output_file`, with it they are embedded and upserted to Qdrant instead.
+pub fn decorate_passages(mut passages: DocStream, embed: bool) {
+    let settings = &config().decorate;
+    let incremental = config().progress.incremental;
+    let manifest_file = if embed { &settings.embed_manifest_file } else { &settings.manifest_file };
+    let progress_file = if embed { &settings.embed_progress_file } else { &settings.progress_file };
+    let mut manifest = Manifest::default();
+    let mut hashes = HashMap::new();
+    if incremental {
+        manifest = Manifest::load(manifest_file);
+        let stale_points;
+        (passages, hashes, stale_points) = match prepare_incremental(passages, &mut manifest) {
+            Ok(r) => r,
+            Err(e) => panic!("Can't compare docs against the manifest: {:#?}", e),
+        };
+        let rt = Runtime::new().unwrap();
+        if let Err(e) = rt.block_on(async { delete_points(stale_points).await }) {
+            panic!("Can't remove stale passages from Qdrant: {:#?}", e);
+        }
+    }
+    println!("Passages to decorate: {}", passages.remaining_files());
     let device1 = match Device::new_cuda(0) {
         Ok(cuda) => cuda,
         Err(e) => {
@@ -30,40 +52,82 @@ pub fn decorate_passages(mut passages: Vec<Doc>) -> Vec<ProcessedDocumentChunk>
         },
     };
 
-    let tokenizer = match load_tokenizer(KEYWORD_DECORATOR_TOKENIZER) {
+    let tokenizer = match load_tokenizer(&settings.tokenizer) {
         Ok(t) => t,
         Err(e) => panic!("Can't load tokenizer: {:#?}", e),
     };
 
-    let model1 = match load_model(KEYWORD_DECORATOR_MODEL, &device1) { 
+    let splitter = match build_splitter(&settings.splitter) {
+        Ok(s) => s,
+        Err(e) => panic!("Can't build splitter: {:#?}", e),
+    };
+
+    let window = match embed {
+        true => match EmbeddingWindow::load() {
+            Ok(w) => Some(w),
+            Err(e) => panic!("Can't load embedding model limits: {:#?}", e),
+        },
+        false => None,
+    };
+
+    // Loaded once and shared by the batch threads, `forward` only reads the weights
+    let embedding_model = match embed {
+        true => match (load_bert_model(embedding_gpu()), load_embedding_max_tokens()) {
+            (Ok(model), Ok(max_tokens)) => Some((model, max_tokens)),
+            (Err(e), _) | (_, Err(e)) => panic!("Can't load embedding model: {:#?}", e),
+        },
+        false => None,
+    };
+
+    let parents = match Parents::build(&settings.parents) {
+        Ok(p) => p,
+        Err(e) => panic!("Can't build parent splitter: {:#?}", e),
+    };
+
+    let filter = match config().filter.enabled {
+        true => match ChunkFilter::load(&config().filter) {
+            Ok(f) => Some(f),
+            Err(e) => panic!("Can't load chunk filter: {:#?}", e),
+        },
+        false => None,
+    };
+
+    let model1 = match load_model(&settings.model, &device1) { 
         Ok(m) => Arc::new(Mutex::new(m)),
         Err(e) => panic!("Can't load model: {:#?}", e),
     };
 
-    let model2 = match load_model(KEYWORD_DECORATOR_MODEL, &device2) { 
+    let model2 = match load_model(&settings.model, &device2) { 
         Ok(m) => Arc::new(Mutex::new(m)),
         Err(e) => panic!("Can't load model: {:#?}", e),
     };
 
-    let mut progress: Progress = load_progress(KEYWORD_DECORATOR_PROGRESS_FILE);
-
+    let mut progress: Progress = load_progress(progress_file);
+    // The manifest already leaves out files finished in earlier runs
+    if incremental {
+        progress.batches_done = 0;
+    }
     
     let to_process = if let Some(bound) = progress.files_to_process {
-        min(bound, passages.len())
+        min(bound, passages.remaining_files())
     } else {
-        passages.len()
+        passages.remaining_files()
     };
 
     let mut done = progress.batches_done * progress.par_chunk_size;
     let progress_bar = get_progress_bar(to_process, 0);
     progress_bar.inc(done); 
-    let mut data = vec![];
     
-    passages.drain(0..(done as usize));
+    let mut passages = passages.skip_docs(done as usize);
+    let mut failed = HashSet::new();
 
-    for batch in passages.chunks(progress.par_chunk_size as usize) {
+    loop {
+        let batch = passages.next_batch(progress.par_chunk_size as usize);
+        if batch.is_empty() {
+            break;
+        }
 
-        let decorated_docs: Vec<Vec<ProcessedDocumentChunk>> = batch.par_iter().enumerate().map(|(index, document)| {
+        let decorated_docs: Vec<DecoratedDoc> = batch.par_iter().enumerate().map(|(index, document)| {
 
             // Select the appropriate model and device based on the index
             let (mut model, device) = match index % 2 {
@@ -72,104 +136,163 @@ pub fn decorate_passages(mut passages: Vec<Doc>) -> Vec<ProcessedDocumentChunk>
             };
 
             let mut responses: Vec<ProcessedDocumentChunk> = vec![]; 
-            let prompts = split_to_prompts(document);
-            let prompts_len = prompts.len();
-            let doc_progress = get_progress_bar(prompts_len, 1);
+            let chunks: Vec<_> = splitter
+                .split_located(document)
+                .into_iter()
+                .filter(|(text, provenance)| filter.as_ref().is_none_or(|f| f.keep(text, &provenance.source, provenance.index)))
+                .collect();
+            let doc_progress = get_progress_bar(chunks.len(), 1);
             
-            for prompt_string in prompts {
+            for (text, provenance) in chunks {
                 // Process the prompt with the selected model and device
-                let question = prompt_string.clone();
-                let prompt = Prompt::One(
-                    KEYWORD_DECORATOR_SYSTEM_MSG.to_string(),
-                    format!(
-                        "Name of the file: {}\nPassage: {}\n\n Response template: 'KW: <kw1>, <kw2>, <kw3>,...'", 
-                        document.file_name, 
-                        prompt_string
-                    )
-                );
-                match prompt_model(&mut *model, &tokenizer, prompt, device) {
-                    Ok(out) => responses.push((question, out, true)),
-                    Err(e) => responses.push((question, e.to_string(), false)),
+                let question = with_breadcrumb(&provenance.headings, &text);
+                let prompt = keyword_prompt(&document.file_name, &question);
+                let (output, success) = match prompt_model(&mut *model, &tokenizer, prompt, device) {
+                    Ok(out) => (out, true),
+                    Err(e) => (e.to_string(), false),
                 };
+                responses.push(ProcessedDocumentChunk { input: question, output, success, provenance });
                 doc_progress.inc(1);
             }
 
-            if EMBEDD {
+            let mut succeeded = responses.iter().all(|r| r.success);
+
+            if let Some(((model, model_tokenizer, model_device), max_tokens)) = &embedding_model {
 
                 let mut embedded_docs = vec![];
+                let mut points = vec![];
 
                 let rt = Runtime::new().unwrap();  // Create a new Tokio runtime
-                for (passage, keywords, success) in responses.clone() {
+                let chunks: Vec<Provenance> = responses.iter().map(|r| r.provenance.clone()).collect();
+                let parents = parents.of(document, &chunks);
+                for (ProcessedDocumentChunk { input: passage, output: keywords, success, provenance }, parent) in responses.clone().into_iter().zip(parents) {
                     if success {
-                        let content = format!("{}\n\n{}", keywords, passage);
-                        let embedding_vector = match rt.block_on(async { embedd(&content).await }) {
-                            Ok(vec) => vec.to_vec2::<f32>(),
-                            Err(e) => {
-                                println!("Ccant embedd passage: {:#?}\n{}",e, passage);
-                                continue;
-                            },
+                        let content = match &window {
+                            Some(window) => window.keyword_text(&keywords, &passage, &document.relative_path),
+                            None => format!("{}\n\n{}", keywords, passage),
                         };
-
-                        let embedding_vector = match embedding_vector {
+                        let vector = match embedd_with(model, model_tokenizer, model_device, &content, *max_tokens) {
                             Ok(v) => v,
-                            Err(e) =>  {
-                                println!("Cant convert passage embedding tensor: {:#?}\n{}",e, passage);
+                            Err(e) => {
+                                println!("Cant embedd passage: {:#?}\n{}", e, passage);
+                                succeeded = false;
                                 continue;
                             },
                         };
 
-                        let vector = embedding_vector.get(0).unwrap().clone();
-
+                        let id = chunk_id(&document.id, provenance.index);
+                        points.push(ManifestOutput::Point { id: id.clone() });
                         embedded_docs.push(EmbeddedDoc {
+                            id,
                             vector,
                             content: Passage {
                                 usage: 0,
                                 text: content,
+                                doc_id: document.id.clone(),
+                                source: document.relative_path.clone(),
+                                index: provenance.index,
+                                headings: provenance.headings,
+                                pages: document.pages_in(provenance.span.start_byte, provenance.span.end_byte),
+                                span: provenance.span,
+                                strategy: provenance.strategy,
+                                parent,
+                                metadata: document.metadata.clone(),
+                                // Only a pipeline `dedup` stage finds alternate sources
+                                ..Default::default()
                             }
                         });
                     }
                 }
 
-                match rt.block_on(async { insert_docs(embedded_docs.clone()).await }) {
-                    Ok(_) => (),
+                // A failed upsert leaves the file partial in the manifest, so it's retried on the next run
+                return match rt.block_on(async { insert_docs(embedded_docs.clone()).await }) {
+                    Ok(_) => (document.relative_path.clone(), responses, Some(points), succeeded),
                     Err(e) => {
                         println!("Error upserting to Qdrant: {:#?}", e);
-                        ()
+                        (document.relative_path.clone(), responses, None, false)
                     },
                 };
             }
 
-            responses
+            let records = responses
+                .iter()
+                .map(|r| json_line(r).map(|line| ManifestOutput::Record {
+                    file: settings.output_file.clone(),
+                    hash: hash_bytes(line.as_bytes()),
+                }))
+                .collect::<Result<Vec<ManifestOutput>, _>>();
+            match records {
+                Ok(records) => (document.relative_path.clone(), responses, Some(records), succeeded),
+                Err(e) => {
+                    println!("Can't serialize the passages of {}: {:#?}", document.relative_path, e);
+                    (document.relative_path.clone(), responses, None, false)
+                },
+            }
         }).collect();
 
-        data.push(decorated_docs);
+        for (relative_path, records, mut outputs, succeeded) in decorated_docs {
+            if !embed {
+                if let Err(e) = save_to_json(&records, &settings.output_file) {
+                    println!("Failed saving records: {:#?}", e);
+                    outputs = None;
+                }
+            }
+            let hash = match hashes.get(&relative_path) {
+                Some(h) => h,
+                None => continue,
+            };
+            // A file is done once every chunk of every document made from it (e.g. table rows) is,
+            // files with failed chunks stay partial and are processed again on the next run
+            if !succeeded {
+                println!("Some passages of {} failed, it will be processed again on the next run", relative_path);
+                failed.insert(relative_path.clone());
+            }
+            match outputs {
+                Some(outputs) => {
+                    let complete = !failed.contains(&relative_path) && !passages.has_pending(&relative_path);
+                    manifest.record(&relative_path, hash, outputs, complete);
+                },
+                None => {
+                    failed.insert(relative_path.clone());
+                    manifest.record(&relative_path, hash, vec![], false);
+                },
+            }
+        }
 
-        progress_bar.inc(PAR_CHUNK_SIZE); 
+        progress_bar.inc(progress.par_chunk_size); 
         done += progress.par_chunk_size;
 
         progress.batches_done += 1;
 
 
-        if let Err(e) = save_progress(&progress, KEYWORD_DECORATOR_PROGRESS_FILE) {
+        if let Err(e) = save_progress(&progress, progress_file) {
             println!("Failed to save progress file: {:#?}", e);
         }
 
-        if done >= to_process as u64 {
-            break;
+        if incremental {
+            if let Err(e) = manifest.save(manifest_file) {
+                println!("Failed to save manifest: {:#?}", e);
+            }
         }
-    }
 
-    let mut flat_data = vec![];
-
-    for i in data {
-        for j in i {
-            for k in j {
-                flat_data.push(k);
-            }
+        // Only an explicit bound stops early, as spreadsheets yield a document per row
+        if progress.files_to_process.is_some_and(|bound| done >= bound as u64) {
+            break;
         }
     }
 
     progress_bar.finish_with_message("Deorating passages complete!");
-    flat_data
+    passages.report().print();
+}
+
+pub fn keyword_prompt(file_name: &str, passage: &str) -> Prompt {
+    Prompt::One(
+        config().decorate.system_msg.clone(),
+        format!(
+            "Name of the file: {}\nPassage: {}\n\n Response template: 'KW: <kw1>, <kw2>, <kw3>,...'", 
+            file_name, 
+            passage
+        )
+    )
 }
 
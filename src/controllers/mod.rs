@@ -1,4 +1,9 @@
 pub mod translator;
 pub mod splitter;
+pub mod cleaner;
+pub mod dedup;
+pub mod chunk_filter;
 pub mod keyword_decorator;
-pub mod embdding_ft_dataset_generator;
\ No newline at end of file
+pub mod embdding_ft_dataset_generator;
+pub mod pipeline;
+pub mod planner;
\ No newline at end of file
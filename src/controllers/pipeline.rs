@@ -0,0 +1,485 @@
+use std::{fs, path::Path};
+use anyhow::{Error, Result};
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use serde::Deserialize;
+use tokio::runtime::Runtime;
+use crate::{
+    config::{config, SplitterConfig},
+    docs::{chunk::Chunk, doc::{Doc, PageSpan}, embedded_doc::{EmbeddedDoc, Passage}, language::normalize_language, loader::{load_data, load_jsonl_records}, qdant::insert_docs, saver::{save_jsonl, save_raw}},
+    llm::{embedding_model::embedd_with, loader::{embedding_gpu, load_bert_model, load_embedding_max_tokens}, model::ModelPair, prompt::prompt_model, tokenizer::load_tokenizer},
+    util::get_progress_bar
+};
+use super::{
+    chunk_filter::ChunkFilter,
+    cleaner::clean_docs,
+    dedup::dedup_chunks,
+    keyword_decorator::keyword_prompt,
+    splitter::{build_splitter, with_breadcrumb, EmbeddingWindow},
+    translator::{needs_translation, translation_prompt}
+};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PipelineSpec {
+    /// Folder with documents, used when the first stage to run consumes documents
+    pub input_folder: Option<String>,
+    /// JSONL file with chunks, used when the first stage to run consumes chunks
+    pub input_chunks: Option<String>,
+    pub stages: Vec<StageSpec>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct StageSpec {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: StageKind,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StageKind {
+    Translate,
+    Clean,
+    Split {
+        /// Same keys as the `splitter` config sections
+        #[serde(flatten)]
+        splitter: SplitterConfig,
+    },
+    Dedup,
+    /// Drops low quality chunks with the `filter` config section rules
+    Filter,
+    Decorate,
+    Embed,
+    Upsert,
+    Save {
+        path: String,
+    },
+}
+
+#[derive(Debug)]
+pub enum PipelineData {
+    Docs(Vec<Doc>),
+    Chunks(Vec<Chunk>),
+    Embedded(Vec<EmbeddedDoc>),
+}
+
+impl PipelineData {
+    fn describe(&self) -> String {
+        match self {
+            PipelineData::Docs(d) => format!("{} documents", d.len()),
+            PipelineData::Chunks(c) => format!("{} chunks", c.len()),
+            PipelineData::Embedded(e) => format!("{} embedded passages", e.len()),
+        }
+    }
+}
+
+impl PipelineSpec {
+    pub fn from_file(file_name: &str) -> Result<Self> {
+        let contents = fs::read_to_string(file_name)
+            .map_err(|e| Error::msg(format!("Can't read pipeline file {}: {}", file_name, e)))?;
+        Ok(toml::from_str(&contents)?)
+    }
+
+    /// Returns the stages to run, keeping the order of the spec. An empty
+    /// selection runs every stage.
+    pub fn select_stages(&self, selected: &[String]) -> Result<Vec<&StageSpec>> {
+        for name in selected {
+            if !self.stages.iter().any(|s| &s.name == name) {
+                return Err(Error::msg(format!("Pipeline has no stage named '{}'", name)));
+            }
+        }
+        Ok(self.stages
+            .iter()
+            .filter(|s| selected.is_empty() || selected.contains(&s.name))
+            .collect())
+    }
+}
+
+pub fn run_pipeline(spec: &PipelineSpec, selected: &[String]) -> Result<PipelineData> {
+    let stages = spec.select_stages(selected)?;
+    let first = match stages.first() {
+        Some(s) => s,
+        None => return Err(Error::msg("Pipeline has no stages to run")),
+    };
+
+    let mut data = load_input(spec, &first.kind)?;
+    println!("Pipeline input: {}", data.describe());
+
+    for stage in stages {
+        println!("Running stage '{}'...", stage.name);
+        data = run_stage(stage, data)?;
+        println!("Stage '{}' produced {}", stage.name, data.describe());
+    }
+    Ok(data)
+}
+
+fn load_input(spec: &PipelineSpec, first_stage: &StageKind) -> Result<PipelineData> {
+    match first_stage {
+        StageKind::Dedup | StageKind::Filter | StageKind::Decorate | StageKind::Embed => match &spec.input_chunks {
+            Some(file) => Ok(PipelineData::Chunks(load_jsonl_records(file)?)),
+            None => Err(Error::msg("First stage consumes chunks, but the pipeline has no `input_chunks`")),
+        },
+        StageKind::Upsert => Err(Error::msg("Pipeline can't start with the upsert stage")),
+        _ => match &spec.input_folder {
+            Some(folder) => Ok(PipelineData::Docs(load_data(folder)?)),
+            None => Err(Error::msg("First stage consumes documents, but the pipeline has no `input_folder`")),
+        },
+    }
+}
+
+fn run_stage(stage: &StageSpec, data: PipelineData) -> Result<PipelineData> {
+    match (&stage.kind, data) {
+        (StageKind::Translate, PipelineData::Docs(docs)) => Ok(PipelineData::Docs(translate_docs(docs)?)),
+        (StageKind::Clean, PipelineData::Docs(docs)) => Ok(PipelineData::Docs(clean_docs(docs))),
+        (StageKind::Split { splitter }, PipelineData::Docs(docs)) => Ok(PipelineData::Chunks(split_docs(&docs, splitter)?)),
+        (StageKind::Dedup, PipelineData::Chunks(chunks)) => {
+            let (kept, report) = dedup_chunks(chunks);
+            println!("Removed {} exact and {} near duplicate chunks", report.exact, report.near);
+            Ok(PipelineData::Chunks(kept))
+        },
+        (StageKind::Filter, PipelineData::Chunks(chunks)) => {
+            let (kept, report) = ChunkFilter::load(&config().filter)?.filter_chunks(chunks);
+            println!(
+                "Dropped {} chunks: {} with too few letters, {} with too few tokens, {} matching deny patterns, {} tables of contents",
+                report.total(),
+                report.low_alpha,
+                report.too_few_tokens,
+                report.denied,
+                report.toc
+            );
+            Ok(PipelineData::Chunks(kept))
+        },
+        (StageKind::Decorate, PipelineData::Chunks(chunks)) => Ok(PipelineData::Chunks(decorate_chunks(chunks)?)),
+        (StageKind::Embed, PipelineData::Chunks(chunks)) => Ok(PipelineData::Embedded(embed_chunks(chunks)?)),
+        (StageKind::Upsert, PipelineData::Embedded(embedded)) => {
+            let rt = Runtime::new()?;
+            rt.block_on(async { insert_docs(embedded.clone()).await })?;
+            Ok(PipelineData::Embedded(embedded))
+        },
+        (StageKind::Save { path }, data) => {
+            save_data(&data, path)?;
+            Ok(data)
+        },
+        (kind, data) => Err(Error::msg(format!(
+            "Stage '{}' ({:?}) can't consume {}",
+            stage.name,
+            kind,
+            data.describe()
+        ))),
+    }
+}
+
+fn translate_docs(docs: Vec<Doc>) -> Result<Vec<Doc>> {
+    let settings = &config().translate;
+    let tokenizer = load_tokenizer(&settings.tokenizer)?;
+    let models = ModelPair::load(&settings.model)?;
+    let splitter = build_splitter(&settings.splitter)?;
+    let progress_bar = get_progress_bar(docs.len(), 0);
+    let mut translated = vec![];
+
+    for batch in docs.chunks(config().progress.par_chunk_size as usize) {
+        let results: Vec<Doc> = batch.par_iter().enumerate().map(|(index, document)| {
+            if !needs_translation(document) {
+                return document.clone();
+            }
+            let (mut model, device) = models.get(index);
+            let sections = splitter.split(document);
+            let doc_progress = get_progress_bar(sections.len(), 1);
+            let mut content = String::new();
+            let mut pages = vec![];
+
+            // Same merging as `merge_parsed_documents`, but keeping track of the source pages
+            for section in sections {
+                let (start, end) = section.range;
+                match prompt_model(&mut model, &tokenizer, translation_prompt(section.text), device) {
+                    Ok(out) => {
+                        content.push('\n');
+                        let translated_start = content.len();
+                        content.push_str(&out);
+                        if let Some(page) = document.pages_in(start, end).first() {
+                            pages.push(PageSpan { page: *page, start: translated_start, end: content.len() });
+                        }
+                    },
+                    Err(e) => println!("Failed translating passage from {}: {:#?}", document.file_name, e),
+                };
+                doc_progress.inc(1);
+            }
+
+            let mut metadata = document.metadata.clone();
+            metadata.language = Some(normalize_language(&settings.target_language));
+            Doc {
+                content,
+                pages,
+                metadata,
+                ..document.clone()
+            }
+        }).collect();
+
+        progress_bar.inc(batch.len() as u64);
+        translated.extend(results);
+    }
+
+    progress_bar.finish_with_message("Translation stage complete!");
+    Ok(translated)
+}
+
+fn split_docs(docs: &[Doc], settings: &SplitterConfig) -> Result<Vec<Chunk>> {
+    let splitter = build_splitter(settings)?;
+    let progress_bar = get_progress_bar(docs.len(), 0);
+    let chunks = docs
+        .par_iter()
+        .map(|document| {
+            progress_bar.inc(1);
+            splitter
+                .split_located(document)
+                .into_iter()
+                .map(|(text, provenance)| Chunk {
+                    doc_id: document.id.clone(),
+                    index: provenance.index,
+                    file_name: document.file_name.clone(),
+                    relative_path: provenance.source,
+                    text,
+                    headings: provenance.headings,
+                    pages: document.pages_in(provenance.span.start_byte, provenance.span.end_byte),
+                    span: provenance.span,
+                    strategy: provenance.strategy,
+                    keywords: None,
+                    metadata: document.metadata.clone(),
+                    alternate_sources: vec![],
+                })
+                .collect::<Vec<Chunk>>()
+        })
+        .flatten()
+        .collect();
+    progress_bar.finish_with_message("Splitting stage complete!");
+    Ok(chunks)
+}
+
+fn decorate_chunks(chunks: Vec<Chunk>) -> Result<Vec<Chunk>> {
+    let settings = &config().decorate;
+    let tokenizer = load_tokenizer(&settings.tokenizer)?;
+    let models = ModelPair::load(&settings.model)?;
+    let progress_bar = get_progress_bar(chunks.len(), 0);
+    let mut decorated = vec![];
+
+    for batch in chunks.chunks(config().progress.par_chunk_size as usize) {
+        let results: Vec<Chunk> = batch.par_iter().enumerate().map(|(index, chunk)| {
+            let (mut model, device) = models.get(index);
+            let prompt = keyword_prompt(&chunk.file_name, &with_breadcrumb(&chunk.headings, &chunk.text));
+            // Chunks that fail are kept without keywords, so they're still embedded
+            let keywords = match prompt_model(&mut model, &tokenizer, prompt, device) {
+                Ok(out) => Some(out),
+                Err(e) => {
+                    println!("Failed decorating passage {} from {}, keeping it without keywords: {:#?}", chunk.index, chunk.file_name, e);
+                    None
+                },
+            };
+            Chunk {
+                keywords,
+                ..chunk.clone()
+            }
+        }).collect();
+
+        progress_bar.inc(batch.len() as u64);
+        decorated.extend(results);
+    }
+
+    progress_bar.finish_with_message("Decorating stage complete!");
+    Ok(decorated)
+}
+
+fn embed_chunks(chunks: Vec<Chunk>) -> Result<Vec<EmbeddedDoc>> {
+    // Loaded once for the whole stage
+    let (model, tokenizer, device) = load_bert_model(embedding_gpu())?;
+    let max_tokens = load_embedding_max_tokens()?;
+    let window = EmbeddingWindow::load()?;
+    let progress_bar = get_progress_bar(chunks.len(), 0);
+    let mut embedded_docs = vec![];
+
+    for chunk in chunks {
+        progress_bar.inc(1);
+        let text = with_breadcrumb(&chunk.headings, &chunk.text);
+        let content = match &chunk.keywords {
+            Some(keywords) => window.keyword_text(keywords, &text, &chunk.relative_path),
+            None => text,
+        };
+        let vector = match embedd_with(&model, &tokenizer, &device, &content, max_tokens) {
+            Ok(v) => v,
+            Err(e) => {
+                println!("Cant embedd passage: {:#?}\n{}", e, chunk.text);
+                continue;
+            },
+        };
+
+        embedded_docs.push(EmbeddedDoc {
+            id: chunk.id(),
+            vector,
+            content: Passage {
+                usage: 0,
+                text: content,
+                doc_id: chunk.doc_id.clone(),
+                source: chunk.relative_path.clone(),
+                index: chunk.index,
+                headings: chunk.headings.clone(),
+                pages: chunk.pages.clone(),
+                span: chunk.span.clone(),
+                strategy: chunk.strategy,
+                parent: None,
+                alternate_sources: chunk.alternate_sources.clone(),
+                metadata: chunk.metadata.clone(),
+            }
+        });
+    }
+
+    progress_bar.finish_with_message("Embedding stage complete!");
+    Ok(embedded_docs)
+}
+
+fn save_data(data: &PipelineData, path: &str) -> Result<()> {
+    match data {
+        PipelineData::Docs(docs) => {
+            for doc in docs {
+                let name = if !doc.relative_path.is_empty() {
+                    doc.relative_path.clone()
+                } else {
+                    match Path::new(&doc.file_name).file_name() {
+                        Some(n) => n.to_string_lossy().to_string(),
+                        None => format!("{}.md", doc.id),
+                    }
+                };
+                let target = Path::new(path).join(name);
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                save_raw(doc.content.clone(), target.to_string_lossy().to_string())?;
+            }
+            Ok(())
+        },
+        PipelineData::Chunks(chunks) => save_jsonl(chunks, path),
+        PipelineData::Embedded(embedded) => save_jsonl(embedded, path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec() -> PipelineSpec {
+        toml::from_str(r#"
+            input_folder = "data/in"
+
+            [[stages]]
+            name = "clean"
+            kind = "clean"
+
+            [[stages]]
+            name = "split"
+            kind = "split"
+            strategy = "markdown"
+
+            [[stages]]
+            name = "dedup"
+            kind = "dedup"
+
+            [[stages]]
+            name = "save"
+            kind = "save"
+            path = "data/chunks.jsonl"
+        "#).unwrap()
+    }
+
+    fn names(stages: Vec<&StageSpec>) -> Vec<&str> {
+        stages.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    fn stage(name: &str, kind: StageKind) -> StageSpec {
+        StageSpec { name: name.to_string(), kind }
+    }
+
+    fn doc(relative_path: &str, content: &str) -> Doc {
+        Doc {
+            id: relative_path.to_string(),
+            content: content.to_string(),
+            file_name: relative_path.to_string(),
+            relative_path: relative_path.to_string(),
+            pages: vec![],
+            metadata: Default::default(),
+        }
+    }
+
+    fn chunk(relative_path: &str, text: &str) -> Chunk {
+        Chunk {
+            doc_id: relative_path.to_string(),
+            index: 0,
+            file_name: relative_path.to_string(),
+            relative_path: relative_path.to_string(),
+            text: text.to_string(),
+            headings: vec![],
+            span: Default::default(),
+            strategy: Default::default(),
+            keywords: None,
+            pages: vec![],
+            metadata: Default::default(),
+            alternate_sources: vec![],
+        }
+    }
+
+    #[test]
+    fn selected_stages_keep_the_spec_order() {
+        let spec = spec();
+        assert_eq!(names(spec.select_stages(&[]).unwrap()), ["clean", "split", "dedup", "save"]);
+        let selected = ["save".to_string(), "split".to_string()];
+        assert_eq!(names(spec.select_stages(&selected).unwrap()), ["split", "save"]);
+        assert!(matches!(spec.stages[1].kind, StageKind::Split { .. }));
+    }
+
+    #[test]
+    fn unknown_stages_are_errors() {
+        let error = spec().select_stages(&["clean".to_string(), "embed".to_string()]).unwrap_err();
+        assert_eq!(error.to_string(), "Pipeline has no stage named 'embed'");
+    }
+
+    #[test]
+    fn stages_transform_matching_data() {
+        let data = PipelineData::Docs(vec![doc("a.md", "Naslov  \n\n\n\nBesedilo")]);
+        let data = run_stage(&stage("clean", StageKind::Clean), data).unwrap();
+        assert!(matches!(&data, PipelineData::Docs(docs) if docs[0].content == "Naslov\n\nBesedilo"));
+
+        let data = PipelineData::Chunks(vec![chunk("a.md", "Isti odstavek."), chunk("b.md", "Isti odstavek.")]);
+        let data = run_stage(&stage("dedup", StageKind::Dedup), data).unwrap();
+        assert!(matches!(&data, PipelineData::Chunks(chunks) if chunks.len() == 1));
+    }
+
+    #[test]
+    fn stages_reject_data_of_another_type() {
+        let error = run_stage(&stage("embed", StageKind::Embed), PipelineData::Docs(vec![doc("a.md", "Besedilo")])).unwrap_err();
+        assert_eq!(error.to_string(), "Stage 'embed' (Embed) can't consume 1 documents");
+
+        let error = run_stage(&stage("clean", StageKind::Clean), PipelineData::Chunks(vec![])).unwrap_err();
+        assert_eq!(error.to_string(), "Stage 'clean' (Clean) can't consume 0 chunks");
+
+        let error = run_stage(&stage("upsert", StageKind::Upsert), PipelineData::Chunks(vec![])).unwrap_err();
+        assert_eq!(error.to_string(), "Stage 'upsert' (Upsert) can't consume 0 chunks");
+    }
+
+    #[test]
+    fn save_writes_any_data() {
+        let folder = std::env::temp_dir().join(format!("pipeline_save_{}", std::process::id()));
+        let path = folder.to_string_lossy().to_string();
+        let data = PipelineData::Docs(vec![doc("sub/a.md", "Besedilo")]);
+        run_stage(&stage("save", StageKind::Save { path: path.clone() }), data).unwrap();
+        assert_eq!(fs::read_to_string(folder.join("sub/a.md")).unwrap(), "Besedilo");
+
+        let file = folder.join("chunks.jsonl").to_string_lossy().to_string();
+        run_stage(&stage("save", StageKind::Save { path: file.clone() }), PipelineData::Chunks(vec![chunk("a.md", "Besedilo")])).unwrap();
+        let chunks: Vec<Chunk> = load_jsonl_records(&file).unwrap();
+        assert_eq!(chunks[0].text, "Besedilo");
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn the_first_stage_picks_the_input() {
+        let error = load_input(&spec(), &StageKind::Dedup).unwrap_err();
+        assert_eq!(error.to_string(), "First stage consumes chunks, but the pipeline has no `input_chunks`");
+        assert!(load_input(&spec(), &StageKind::Upsert).is_err());
+    }
+}
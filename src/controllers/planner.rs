@@ -0,0 +1,287 @@
+use anyhow::{Error, Result};
+use candle_transformers::models::quantized_llama::MAX_SEQ_LEN;
+use clap::ValueEnum;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use tokenizers::Tokenizer;
+use crate::{
+    config::config,
+    docs::doc::Doc,
+    llm::{model::GPU_COUNT, prompt::{parse_prompt_to_raw, Prompt}, tokenizer::load_tokenizer},
+    util::get_progress_bar
+};
+use super::{
+    chunk_filter::ChunkFilter,
+    embdding_ft_dataset_generator::question_prompt,
+    keyword_decorator::keyword_prompt,
+    splitter::{build_estimating_splitter, with_breadcrumb, SplitStrategy, Splitter},
+    translator::{needs_translation, translation_prompt}
+};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum PlanTarget {
+    Translate,
+    Decorate,
+    GenerateQuestions,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct FilePlan {
+    pub file_name: String,
+    pub chunks: usize,
+    pub dropped: usize,
+    pub prompts: usize,
+    pub prompt_tokens: usize,
+    pub max_prompt_tokens: usize,
+    pub truncated: usize,
+}
+
+pub fn input_folder(target: PlanTarget) -> String {
+    match target {
+        PlanTarget::Translate => config().translate.input_folder.clone(),
+        PlanTarget::Decorate => config().decorate.input_folder.clone(),
+        PlanTarget::GenerateQuestions => config().questions.input_folder.clone(),
+    }
+}
+
+pub fn plan(docs: &[Doc], target: PlanTarget) -> Result<Vec<FilePlan>> {
+    let (tokenizer_path, splitter_settings) = match target {
+        PlanTarget::Translate => (&config().translate.tokenizer, &config().translate.splitter),
+        PlanTarget::Decorate => (&config().decorate.tokenizer, &config().decorate.splitter),
+        PlanTarget::GenerateQuestions => (&config().questions.tokenizer, &config().questions.splitter),
+    };
+    let tokenizer = load_tokenizer(tokenizer_path)?;
+    // Semantic splitting embeds the documents, so its topic shifts are estimated instead
+    if splitter_settings.strategy == SplitStrategy::Semantic {
+        println!("Estimating semantic chunks from `breakpoint_percentile`, as `plan` doesn't load the embedding model");
+    }
+    let splitter = build_estimating_splitter(splitter_settings)?;
+    // Translation keeps every chunk, so the filter doesn't apply to it
+    let filter = match (target, config().filter.enabled) {
+        (PlanTarget::Translate, _) | (_, false) => None,
+        _ => Some(ChunkFilter::load(&config().filter)?),
+    };
+
+    // Same budget as `prompt_model`: anything above it gets front-truncated.
+    let to_sample = config().generation.sample_len.saturating_sub(1);
+    let token_budget = (MAX_SEQ_LEN - 10).saturating_sub(to_sample);
+
+    let progress_bar = get_progress_bar(docs.len(), 0);
+    let plans = docs
+        .par_iter()
+        .map(|document| {
+            let plan = plan_document(document, target, &*splitter, filter.as_ref(), &tokenizer, token_budget);
+            progress_bar.inc(1);
+            plan
+        })
+        .collect::<Result<Vec<FilePlan>>>()?;
+    progress_bar.finish_with_message("Planning complete!");
+
+    Ok(plans)
+}
+
+fn plan_document(
+    document: &Doc,
+    target: PlanTarget,
+    splitter: &dyn Splitter,
+    filter: Option<&ChunkFilter>,
+    tokenizer: &Tokenizer,
+    token_budget: usize
+) -> Result<FilePlan> {
+    let mut sections = splitter.split(document);
+    let chunks = sections.len();
+    if let Some(filter) = filter {
+        // Chunks that fail to tokenize are kept, like in the commands themselves
+        sections.retain(|s| !matches!(filter.check(&s.text), Ok(Some(_))));
+    }
+
+    let mut plan = FilePlan {
+        file_name: document.file_name.clone(),
+        chunks,
+        dropped: chunks - sections.len(),
+        ..Default::default()
+    };
+
+    let (chunks, build_prompt): (Vec<String>, Box<dyn Fn(String) -> Prompt>) = match target {
+        PlanTarget::Translate if !needs_translation(document) => (vec![], Box::new(translation_prompt)),
+        PlanTarget::Translate => (sections.into_iter().map(|s| s.text).collect(), Box::new(translation_prompt)),
+        PlanTarget::Decorate => {
+            let chunks = sections.iter().map(|s| with_breadcrumb(&s.headings, &s.text)).collect();
+            (chunks, Box::new(|p: String| keyword_prompt(&document.file_name, &p)))
+        },
+        PlanTarget::GenerateQuestions => {
+            let chunks = sections.iter().map(|s| with_breadcrumb(&s.headings, &s.text)).collect();
+            (chunks, Box::new(|p: String| question_prompt(&document.file_name, &p)))
+        },
+    };
+
+    for chunk in chunks {
+        let prompt_str = parse_prompt_to_raw(&build_prompt(chunk))?;
+        let tokens = tokenizer
+            .encode(prompt_str, true)
+            .map_err(Error::msg)?
+            .len();
+        plan.prompts += 1;
+        plan.prompt_tokens += tokens;
+        plan.max_prompt_tokens = plan.max_prompt_tokens.max(tokens);
+        if tokens > token_budget {
+            plan.truncated += 1;
+        }
+    }
+
+    Ok(plan)
+}
+
+pub fn print_plan(plans: &[FilePlan], target: PlanTarget) {
+    let settings = &config().plan;
+    println!(
+        "\n{:>8} {:>8} {:>8} {:>10} {:>8} {:>6}  file",
+        "chunks", "dropped", "prompts", "tokens", "max", "trunc"
+    );
+    let mut total = FilePlan::default();
+    for plan in plans {
+        println!(
+            "{:>8} {:>8} {:>8} {:>10} {:>8} {:>6}  {}",
+            plan.chunks,
+            plan.dropped,
+            plan.prompts,
+            plan.prompt_tokens,
+            plan.max_prompt_tokens,
+            plan.truncated,
+            plan.file_name
+        );
+        total.chunks += plan.chunks;
+        total.dropped += plan.dropped;
+        total.prompts += plan.prompts;
+        total.prompt_tokens += plan.prompt_tokens;
+        total.max_prompt_tokens = total.max_prompt_tokens.max(plan.max_prompt_tokens);
+        total.truncated += plan.truncated;
+    }
+    println!(
+        "{:>8} {:>8} {:>8} {:>10} {:>8} {:>6}  TOTAL ({} files, target: {:?})",
+        total.chunks,
+        total.dropped,
+        total.prompts,
+        total.prompt_tokens,
+        total.max_prompt_tokens,
+        total.truncated,
+        plans.len(),
+        target
+    );
+
+    let generated_tokens = total.prompts * settings.generated_tokens_per_prompt;
+    let seconds = (total.prompt_tokens as f64 / settings.prompt_tokens_per_sec
+        + generated_tokens as f64 / settings.generated_tokens_per_sec)
+        / GPU_COUNT as f64;
+    println!(
+        "\nEstimated ~{} generated tokens, ETA {} on {} GPUs ({:.1} prompt tok/s, {:.1} generated tok/s)",
+        generated_tokens,
+        format_duration(seconds),
+        GPU_COUNT,
+        settings.prompt_tokens_per_sec,
+        settings.generated_tokens_per_sec
+    );
+    if total.truncated > 0 {
+        println!("WARNING: {} prompts exceed the context budget and would be front-truncated!", total.truncated);
+    }
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.max(0.) as u64;
+    let (days, hours, minutes) = (seconds / 86400, (seconds % 86400) / 3600, (seconds % 3600) / 60);
+    if days > 0 {
+        format!("{}d {:02}h {:02}m", days, hours, minutes)
+    } else {
+        format!("{:02}h {:02}m {:02}s", hours, minutes, seconds % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::{FilterConfig, SplitterConfig},
+        controllers::splitter::{estimating_splitter, tests::word_tokenizer}
+    };
+    use super::*;
+
+    // Six paragraphs of 11 tokens, in two sections
+    fn document() -> Doc {
+        let paragraph = |n: usize| format!("Odstavek {n} ima natanko deset besed in se tukaj konča.");
+        let content = format!(
+            "# Vpis\n\n{}\n\n{}\n\n{}\n\n# Izpiti\n\n{}\n\n{}\n\n{}",
+            paragraph(1), paragraph(2), paragraph(3), paragraph(4), paragraph(5), paragraph(6)
+        );
+        Doc {
+            id: "1".to_string(),
+            content,
+            file_name: "pravilnik.md".to_string(),
+            relative_path: "pravilnik.md".to_string(),
+            pages: vec![],
+            metadata: Default::default(),
+        }
+    }
+
+    fn splitter(strategy: SplitStrategy) -> Box<dyn Splitter> {
+        let settings = SplitterConfig { strategy, chunk_tokens: 30, breakpoint_percentile: 50., ..Default::default() };
+        estimating_splitter(word_tokenizer(), settings)
+    }
+
+    fn plan_with(strategy: SplitStrategy, target: PlanTarget, filter: Option<&ChunkFilter>, token_budget: usize) -> FilePlan {
+        plan_document(&document(), target, &*splitter(strategy), filter, &word_tokenizer(), token_budget).unwrap()
+    }
+
+    fn prompt_tokens(prompt: Prompt) -> usize {
+        word_tokenizer().encode(parse_prompt_to_raw(&prompt).unwrap(), true).unwrap().len()
+    }
+
+    #[test]
+    fn every_strategy_is_estimated() {
+        // Paragraphs packed up to 30 tokens, also across headings
+        assert_eq!(plan_with(SplitStrategy::Prompts, PlanTarget::Translate, None, 10_000).chunks, 3);
+        // The last paragraph of each chunk is repeated in the next one
+        assert_eq!(plan_with(SplitStrategy::Overlapping, PlanTarget::Translate, None, 10_000).chunks, 4);
+        // Paragraphs packed within each section
+        assert_eq!(plan_with(SplitStrategy::Markdown, PlanTarget::Translate, None, 10_000).chunks, 4);
+        // Half of the paragraph boundaries taken as topic shifts, within the token limit
+        assert_eq!(plan_with(SplitStrategy::Semantic, PlanTarget::Translate, None, 10_000).chunks, 3);
+    }
+
+    #[test]
+    fn prompt_tokens_count_the_prompts_sent() {
+        let sections = splitter(SplitStrategy::Markdown).split(&document());
+        let translate: Vec<usize> = sections.iter().map(|s| prompt_tokens(translation_prompt(s.text.clone()))).collect();
+        let decorate: Vec<usize> = sections.iter()
+            .map(|s| prompt_tokens(keyword_prompt("pravilnik.md", &with_breadcrumb(&s.headings, &s.text))))
+            .collect();
+
+        let plan = plan_with(SplitStrategy::Markdown, PlanTarget::Translate, None, 10_000);
+        assert_eq!((plan.prompts, plan.prompt_tokens, plan.max_prompt_tokens), (4, translate.iter().sum(), *translate.iter().max().unwrap()));
+        // Decorate prompts also carry the heading breadcrumbs
+        let plan = plan_with(SplitStrategy::Markdown, PlanTarget::Decorate, None, 10_000);
+        assert_eq!((plan.prompts, plan.prompt_tokens, plan.max_prompt_tokens), (4, decorate.iter().sum(), *decorate.iter().max().unwrap()));
+    }
+
+    #[test]
+    fn prompts_over_the_budget_are_truncated() {
+        let max = plan_with(SplitStrategy::Prompts, PlanTarget::Translate, None, 10_000).max_prompt_tokens;
+        assert_eq!(plan_with(SplitStrategy::Prompts, PlanTarget::Translate, None, max).truncated, 0);
+        assert!(plan_with(SplitStrategy::Prompts, PlanTarget::Translate, None, max - 1).truncated > 0);
+        assert_eq!(plan_with(SplitStrategy::Prompts, PlanTarget::Translate, None, 0).truncated, 3);
+    }
+
+    #[test]
+    fn filtered_chunks_are_not_prompted() {
+        let settings = FilterConfig { min_tokens: 15, ..Default::default() };
+        let filter = ChunkFilter::with_tokenizer(&settings, word_tokenizer()).unwrap();
+        // The single paragraph chunks at the end of each section are too short
+        let plan = plan_with(SplitStrategy::Markdown, PlanTarget::Decorate, Some(&filter), 10_000);
+        assert_eq!((plan.chunks, plan.dropped, plan.prompts), (4, 2, 2));
+    }
+
+    #[test]
+    fn documents_in_the_target_language_are_not_translated() {
+        let mut document = document();
+        document.metadata.language = Some(config().translate.target_language.clone());
+        let plan = plan_document(&document, PlanTarget::Translate, &*splitter(SplitStrategy::Prompts), None, &word_tokenizer(), 10_000).unwrap();
+        assert_eq!((plan.chunks, plan.prompts, plan.prompt_tokens), (3, 0, 0));
+    }
+}
@@ -0,0 +1,1001 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokenizers::Tokenizer;
+
+use crate::{
+    config::SplitterConfig,
+    docs::{chunk::{ChunkSpan, ProcessedDocumentChunk, Provenance}, doc::Doc},
+    llm::{loader::{load_embedding_max_tokens, load_embedding_tokenizer}, tokenizer::load_tokenizer}
+};
+
+use semantic::{SemanticEstimate, SemanticSplitter};
+use sentences::sentence_ranges;
+
+pub mod parents;
+mod semantic;
+pub mod sentences;
+
+const BREADCRUMB_SEPARATOR: &str = " › ";
+const CLAUSE_MARKS: [char; 5] = [',', ';', ':', '–', '—'];
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitStrategy {
+    /// Paragraphs packed up to the token limit
+    #[default]
+    Prompts,
+    /// Like `prompts`, repeating the last paragraphs of a chunk at the start of the next one
+    Overlapping,
+    /// Paragraphs packed within top level markdown sections, with heading breadcrumbs
+    Markdown,
+    /// Chunk boundaries where the embeddings of neighbouring sentences or paragraphs diverge
+    Semantic,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SemanticUnit {
+    #[default]
+    Paragraph,
+    Sentence,
+}
+
+/// A chunk of a document with the path of markdown headings it falls under.
+#[derive(Debug, Clone, Default)]
+pub struct SectionChunk {
+    pub text: String,
+    pub headings: Vec<String>,
+    /// Byte range of the document content the chunk was taken from. The text is the
+    /// content of the range, except for table parts that repeat the header row.
+    pub range: (usize, usize),
+}
+
+/// Splits documents into chunks that fit a prompt or an embedding.
+pub trait Splitter: Send + Sync {
+    /// Returns the chunks of the document in document order.
+    fn split(&self, document: &Doc) -> Vec<SectionChunk>;
+
+    /// Strategy the splitter implements, recorded in the provenance of its chunks.
+    fn strategy(&self) -> SplitStrategy;
+
+    /// Returns the text of the chunks with where they were taken from.
+    fn split_located(&self, document: &Doc) -> Vec<(String, Provenance)> {
+        self.split(document)
+            .into_iter()
+            .enumerate()
+            .map(|(index, section)| {
+                let (start, end) = section.range;
+                let provenance = Provenance {
+                    source: document.relative_path.clone(),
+                    index,
+                    span: ChunkSpan::new(&document.content, start, end),
+                    headings: section.headings,
+                    strategy: self.strategy(),
+                };
+                (section.text, provenance)
+            })
+            .collect()
+    }
+}
+
+/// Builds the splitter selected by `settings.strategy`. Without a configured tokenizer
+/// tokens are counted with the embedding model's tokenizer and chunks are also limited
+/// to the model's input length.
+pub fn build_splitter(settings: &SplitterConfig) -> Result<Box<dyn Splitter>> {
+    let mut settings = settings.clone();
+    let tokenizer = splitter_tokenizer(&mut settings)?;
+    Ok(match settings.strategy {
+        SplitStrategy::Semantic => Box::new(SemanticSplitter::new(tokenizer, settings)?),
+        _ => estimating_splitter(tokenizer, settings),
+    })
+}
+
+/// Like `build_splitter`, but estimates `semantic` chunks without loading the embedding
+/// model (see `SemanticEstimate`), for `plan`.
+pub fn build_estimating_splitter(settings: &SplitterConfig) -> Result<Box<dyn Splitter>> {
+    let mut settings = settings.clone();
+    let tokenizer = splitter_tokenizer(&mut settings)?;
+    Ok(estimating_splitter(tokenizer, settings))
+}
+
+/// Builds the splitter of `settings.strategy` around an already loaded tokenizer, with
+/// `semantic` chunks estimated. Only the semantic splitter needs more than the tokenizer.
+pub(crate) fn estimating_splitter(tokenizer: Tokenizer, settings: SplitterConfig) -> Box<dyn Splitter> {
+    match settings.strategy {
+        SplitStrategy::Prompts => Box::new(PromptSplitter { tokenizer, settings }),
+        SplitStrategy::Overlapping => Box::new(OverlappingSplitter { tokenizer, settings }),
+        SplitStrategy::Markdown => Box::new(MarkdownSplitter { tokenizer, settings }),
+        SplitStrategy::Semantic => Box::new(SemanticEstimate { tokenizer, settings }),
+    }
+}
+
+/// Loads the tokenizer the chunks are measured with. Without a configured one, the
+/// chunks are also limited to the input length of the embedding model.
+fn splitter_tokenizer(settings: &mut SplitterConfig) -> Result<Tokenizer> {
+    Ok(match &settings.tokenizer {
+        Some(path) => load_tokenizer(path)?,
+        None => {
+            let max_tokens = load_embedding_max_tokens()?;
+            if settings.chunk_tokens > max_tokens {
+                println!("Limiting chunks to the {} tokens of the embedding model instead of {}", max_tokens, settings.chunk_tokens);
+                settings.chunk_tokens = max_tokens;
+            }
+            load_embedding_tokenizer()?
+        },
+    })
+}
+
+/// Prefixes the text with its heading path, e.g. `Master's programme › Enrollment › Fees`,
+/// so the prompts and embeddings see which section a passage belongs to.
+pub fn with_breadcrumb(headings: &[String], text: &str) -> String {
+    if headings.is_empty() {
+        return text.to_string();
+    }
+    format!("{}\n\n{}", headings.join(BREADCRUMB_SEPARATOR), text)
+}
+
+pub fn merge_parsed_documents(records: Vec<ProcessedDocumentChunk>) -> String {
+    let mut merged = "".to_owned();
+    for record in records {
+        if record.success {
+            merged = format!("{}\n{}", merged, record.output);
+        }
+    }
+    merged
+}
+
+/// Tokenizer and input length of the embedding model, to check that texts fit before embedding them.
+pub struct EmbeddingWindow {
+    tokenizer: Tokenizer,
+    max_tokens: usize,
+}
+
+impl EmbeddingWindow {
+    pub fn load() -> Result<Self> {
+        Ok(Self {
+            tokenizer: load_embedding_tokenizer()?,
+            max_tokens: load_embedding_max_tokens()?,
+        })
+    }
+
+    /// Prefixes the passage with its keywords for embedding. Keyword lines that would push
+    /// the text past the model's input length are dropped from the end, and logged.
+    pub fn keyword_text(&self, keywords: &str, passage: &str, source: &str) -> String {
+        let budget = Budget::new(&self.tokenizer, self.max_tokens, source);
+        let mut lines: Vec<&str> = keywords.lines().collect();
+        let total = lines.len();
+        while !lines.is_empty() && !budget.fits(&format!("{}\n\n{}", lines.join("\n"), passage)) {
+            lines.pop();
+        }
+        if lines.len() < total {
+            println!(
+                "Keyword-prefixed passage from {} exceeds the {} tokens of the embedding model, dropped {} of {} keyword lines",
+                source,
+                self.max_tokens,
+                total - lines.len(),
+                total
+            );
+        }
+        if lines.is_empty() {
+            if !budget.fits(passage) {
+                println!("Passage from {} exceeds the {} tokens of the embedding model", source, self.max_tokens);
+            }
+            return passage.to_string();
+        }
+        format!("{}\n\n{}", lines.join("\n"), passage)
+    }
+}
+
+/// Packs paragraphs (split at blank lines) into chunks of up to `chunk_tokens`.
+pub struct PromptSplitter {
+    tokenizer: Tokenizer,
+    settings: SplitterConfig,
+}
+
+impl Splitter for PromptSplitter {
+    fn strategy(&self) -> SplitStrategy {
+        self.settings.strategy
+    }
+
+    fn split(&self, document: &Doc) -> Vec<SectionChunk> {
+        let budget = Budget::for_document(&self.tokenizer, self.settings.chunk_tokens, document);
+        pack_paragraphs(&document.content, &budget, None)
+    }
+}
+
+/// Packs paragraphs like `PromptSplitter`, but starts every chunk with up to
+/// `overlap_paragraphs` last paragraphs of the previous one, as long as they
+/// take at most `overlap_tokens`.
+pub struct OverlappingSplitter {
+    tokenizer: Tokenizer,
+    settings: SplitterConfig,
+}
+
+impl Splitter for OverlappingSplitter {
+    fn strategy(&self) -> SplitStrategy {
+        self.settings.strategy
+    }
+
+    fn split(&self, document: &Doc) -> Vec<SectionChunk> {
+        let budget = Budget::for_document(&self.tokenizer, self.settings.chunk_tokens, document);
+        pack_paragraphs(&document.content, &budget, Some(&self.settings))
+    }
+}
+
+fn pack_paragraphs(content: &str, budget: &Budget, overlap: Option<&SplitterConfig>) -> Vec<SectionChunk> {
+    let chunk_tokens = budget.max_tokens;
+    let mut chunks = Vec::new();
+    // Paragraphs of the current chunk with their token counts
+    let mut current: Vec<(Piece, usize)> = Vec::new();
+    let mut current_token_count = 0;
+
+    for paragraph in paragraphs(content, budget) {
+        let token_count = budget.count(&paragraph.text);
+
+        if !current.is_empty() && current_token_count + token_count > chunk_tokens {
+            chunks.push(join_pieces(content, current.iter().map(|(piece, _)| piece), vec![]));
+
+            // Tables move to the next chunk whole, so they aren't repeated either
+            let room = chunk_tokens - token_count.min(chunk_tokens);
+            let carried = match overlap {
+                Some(settings) if !is_table(&paragraph.text) => overlap_len(&current, settings, room),
+                _ => 0,
+            };
+            // Without a short enough paragraph to repeat, the last sentences are repeated
+            let tail = match (overlap, current.last()) {
+                (Some(settings), Some((last, _))) if carried == 0 && settings.overlap_paragraphs > 0 && !is_table(&paragraph.text) && !is_table(&last.text) => {
+                    sentence_tail(last, settings.overlap_tokens.min(room), budget)
+                },
+                _ => None,
+            };
+            current.drain(..current.len() - carried);
+            current.extend(tail);
+            current_token_count = current.iter().map(|(_, tokens)| tokens).sum();
+        }
+        current.push((paragraph, token_count));
+        current_token_count += token_count;
+    }
+
+    if !current.is_empty() {
+        chunks.push(join_pieces(content, current.iter().map(|(piece, _)| piece), vec![]));
+    }
+
+    chunks
+}
+
+/// Number of last paragraphs to repeat in the next chunk, leaving `room` tokens for the rest.
+fn overlap_len(paragraphs: &[(Piece, usize)], settings: &SplitterConfig, room: usize) -> usize {
+    let budget = settings.overlap_tokens.min(room);
+    let mut carried = 0;
+    let mut token_count = 0;
+    for (paragraph, tokens) in paragraphs.iter().rev().take(settings.overlap_paragraphs) {
+        if is_table(&paragraph.text) || token_count + tokens > budget {
+            break;
+        }
+        carried += 1;
+        token_count += tokens;
+    }
+    // The whole chunk would be repeated
+    carried.min(paragraphs.len().saturating_sub(1))
+}
+
+/// The last sentences of a paragraph that fit in `max_tokens`.
+fn sentence_tail(paragraph: &Piece, max_tokens: usize, budget: &Budget) -> Option<(Piece, usize)> {
+    let text = &paragraph.text;
+    let mut tail = None;
+    for (start, _) in sentence_ranges(text, (0, text.len()), budget.language).into_iter().rev() {
+        let token_count = budget.count(&text[start..]);
+        if token_count > max_tokens {
+            break;
+        }
+        tail = Some((Piece::slice(text, start, text.len()).shift(paragraph.start), token_count));
+    }
+    tail
+}
+
+/// Splits a markdown document along its heading hierarchy. Paragraphs are packed up to
+/// the token limit, but never across top level sections (those with the highest heading
+/// level used in the document). Each chunk keeps the headings shared by all its paragraphs.
+pub struct MarkdownSplitter {
+    tokenizer: Tokenizer,
+    settings: SplitterConfig,
+}
+
+impl Splitter for MarkdownSplitter {
+    fn strategy(&self) -> SplitStrategy {
+        self.settings.strategy
+    }
+
+    fn split(&self, document: &Doc) -> Vec<SectionChunk> {
+        let budget = Budget::for_document(&self.tokenizer, self.settings.chunk_tokens, document);
+        split_markdown(&document.content, &budget)
+    }
+}
+
+fn split_markdown(content: &str, budget: &Budget) -> Vec<SectionChunk> {
+    let mut chunks: Vec<SectionChunk> = Vec::new();
+    let mut current: Vec<&MarkdownBlock> = Vec::new();
+    let mut current_token_count = 0;
+
+    let mut blocks = markdown_blocks(content);
+    for block in blocks.iter_mut() {
+        block.token_count = budget.count(&content[block.start..block.end]);
+    }
+
+    for block in &blocks {
+        let token_count = block.token_count;
+        // The breadcrumb added before prompting and embedding counts against the chunk
+        let budget = budget.without(breadcrumb_tokens(&block.headings, budget));
+        let chunk_tokens = budget.max_tokens;
+
+        if token_count > chunk_tokens {
+            if !current.is_empty() {
+                chunks.push(section_chunk(content, &current));
+                current.clear();
+                current_token_count = 0;
+            }
+            let text = &content[block.start..block.end];
+            let parts = match block.is_table {
+                true => split_table(text, &budget),
+                false => hard_split(text, &budget),
+            };
+            chunks.extend(parts.into_iter().map(|part| {
+                let part = part.shift(block.start);
+                SectionChunk { text: part.text, headings: block.headings.clone(), range: (part.start, part.end) }
+            }));
+            continue;
+        }
+
+        let new_section = current.last().is_some_and(|last| last.section != block.section);
+        if new_section {
+            chunks.push(section_chunk(content, &current));
+            current.clear();
+            current_token_count = 0;
+        } else if !current.is_empty() && current_token_count + token_count > chunk_tokens {
+            // Headings at the end of a full chunk move on with the text they introduce
+            let split_at = current.iter().rposition(|b| !b.is_heading).map_or(current.len(), |i| i + 1);
+            let carried = current.split_off(split_at);
+            if !current.is_empty() {
+                chunks.push(section_chunk(content, &current));
+            }
+            current_token_count = carried.iter().map(|b| b.token_count).sum();
+            current = carried;
+        }
+        current.push(block);
+        current_token_count += token_count;
+    }
+
+    if !current.is_empty() {
+        chunks.push(section_chunk(content, &current));
+    }
+
+    chunks
+}
+
+/// Paragraph, list or heading line of a markdown document.
+struct MarkdownBlock {
+    start: usize,
+    end: usize,
+    /// Headings the block falls under, including its own if it is one
+    headings: Vec<String>,
+    /// Index of the top level section the block belongs to
+    section: usize,
+    is_heading: bool,
+    is_table: bool,
+    token_count: usize,
+}
+
+impl MarkdownBlock {
+    fn new(start: usize, end: usize, path: &[(usize, String)], section: usize, is_heading: bool, is_table: bool) -> Self {
+        Self {
+            start,
+            end,
+            headings: path.iter().map(|(_, title)| title.clone()).collect(),
+            section,
+            is_heading,
+            is_table,
+            token_count: 0,
+        }
+    }
+}
+
+fn markdown_blocks(content: &str) -> Vec<MarkdownBlock> {
+    let top_level = top_heading_level(content);
+    let mut blocks = Vec::new();
+    let mut path: Vec<(usize, String)> = Vec::new();
+    let mut section = 0;
+    let mut block_start: Option<usize> = None;
+    let mut block_end = 0;
+    let mut block_is_table = false;
+    let mut fence: Option<&str> = None;
+    let mut offset = 0;
+
+    for line in content.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        let trimmed = line.trim_end();
+
+        if let Some(marker) = fence {
+            if trimmed.trim_start().starts_with(marker) {
+                fence = None;
+            }
+        } else if let Some(marker) = fence_marker(trimmed) {
+            fence = Some(marker);
+        } else if trimmed.trim().is_empty() || heading(trimmed).is_some() {
+            if let Some(start) = block_start.take() {
+                blocks.push(MarkdownBlock::new(start, block_end, &path, section, false, block_is_table));
+            }
+            if let Some((level, title)) = heading(trimmed) {
+                if level <= top_level {
+                    section += 1;
+                }
+                path.retain(|(l, _)| *l < level);
+                path.push((level, title));
+                blocks.push(MarkdownBlock::new(line_start, line_start + trimmed.len(), &path, section, true, false));
+            }
+            continue;
+        }
+
+        // Tables are blocks of their own, even without blank lines around them
+        let line_is_table = fence.is_none() && is_table_line(trimmed);
+        if line_is_table != block_is_table {
+            if let Some(start) = block_start.take() {
+                blocks.push(MarkdownBlock::new(start, block_end, &path, section, false, block_is_table));
+            }
+        }
+        if block_start.is_none() {
+            block_start = Some(line_start);
+            block_is_table = line_is_table;
+        }
+        block_end = line_start + trimmed.len();
+    }
+    if let Some(start) = block_start {
+        blocks.push(MarkdownBlock::new(start, block_end, &path, section, false, block_is_table));
+    }
+
+    blocks
+}
+
+fn section_chunk(content: &str, blocks: &[&MarkdownBlock]) -> SectionChunk {
+    let first = blocks[0];
+    let last = blocks[blocks.len() - 1];
+    // Headings shared by every block of the chunk
+    let mut headings = first.headings.clone();
+    for block in &blocks[1..] {
+        let shared = headings.iter().zip(&block.headings).take_while(|(a, b)| a == b).count();
+        headings.truncate(shared);
+    }
+    SectionChunk {
+        text: content[first.start..last.end].to_string(),
+        headings,
+        range: (first.start, last.end),
+    }
+}
+
+fn top_heading_level(content: &str) -> usize {
+    let mut fence: Option<&str> = None;
+    let mut top = usize::MAX;
+    for line in content.lines() {
+        if let Some(marker) = fence {
+            if line.trim_start().starts_with(marker) {
+                fence = None;
+            }
+        } else if let Some(marker) = fence_marker(line) {
+            fence = Some(marker);
+        } else if let Some((level, _)) = heading(line) {
+            top = top.min(level);
+        }
+    }
+    top
+}
+
+fn fence_marker(line: &str) -> Option<&'static str> {
+    let line = line.trim_start();
+    if line.starts_with("```") {
+        Some("```")
+    } else if line.starts_with("~~~") {
+        Some("~~~")
+    } else {
+        None
+    }
+}
+
+/// Parses an ATX heading (`## Title`) into its level and title.
+fn heading(line: &str) -> Option<(usize, String)> {
+    let line = line.trim_start();
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    let rest = &line[level..];
+    if !rest.is_empty() && !rest.starts_with([' ', '\t']) {
+        return None;
+    }
+    let title = rest.trim().trim_end_matches('#').trim();
+    if title.is_empty() {
+        return None;
+    }
+    Some((level, title.to_string()))
+}
+
+/// Text taken from the document content with the byte range it covers. The text is the
+/// content of the range, except for table parts that repeat the header row.
+#[derive(Debug, Clone)]
+struct Piece {
+    text: String,
+    start: usize,
+    end: usize,
+}
+
+impl Piece {
+    fn slice(content: &str, start: usize, end: usize) -> Self {
+        Self { text: content[start..end].to_string(), start, end }
+    }
+
+    /// Moves the range of a piece of a slice starting at `offset` to the whole content.
+    fn shift(self, offset: usize) -> Self {
+        Self { start: self.start + offset, end: self.end + offset, ..self }
+    }
+}
+
+/// Joins pieces in document order with the content between them, so chunks
+/// without table parts are slices of the content.
+fn join_pieces<'a>(content: &str, pieces: impl IntoIterator<Item = &'a Piece>, headings: Vec<String>) -> SectionChunk {
+    let mut text = String::new();
+    let mut range: Option<(usize, usize)> = None;
+    for piece in pieces {
+        if let Some((_, end)) = range {
+            text.push_str(content.get(end..piece.start).unwrap_or("\n\n"));
+        }
+        text.push_str(&piece.text);
+        range = Some((range.map_or(piece.start, |(start, _)| start), piece.end));
+    }
+    SectionChunk { text, headings, range: range.unwrap_or_default() }
+}
+
+/// Token limit for the chunks of a document.
+struct Budget<'a> {
+    tokenizer: &'a Tokenizer,
+    max_tokens: usize,
+    /// Document the chunks are taken from, for the log of forced splits
+    source: &'a str,
+    /// Language of the document, for the sentence segmentation
+    language: Option<&'a str>,
+}
+
+impl<'a> Budget<'a> {
+    fn new(tokenizer: &'a Tokenizer, max_tokens: usize, source: &'a str) -> Self {
+        Self { tokenizer, max_tokens, source, language: None }
+    }
+
+    fn for_document(tokenizer: &'a Tokenizer, max_tokens: usize, document: &'a Doc) -> Self {
+        Self {
+            language: document.metadata.language.as_deref(),
+            ..Self::new(tokenizer, max_tokens, &document.file_name)
+        }
+    }
+
+    fn count(&self, text: &str) -> usize {
+        match self.tokenizer.encode(text, true) {
+            Ok(t) => t.len(),
+            Err(e) => panic!("Error tokenizing: {}", e),
+        }
+    }
+
+    fn fits(&self, text: &str) -> bool {
+        self.count(text) <= self.max_tokens
+    }
+
+    /// The same budget with `tokens` reserved for a prefix, keeping at least one token.
+    fn without(&self, tokens: usize) -> Self {
+        Self {
+            max_tokens: self.max_tokens.saturating_sub(tokens).max(1),
+            ..*self
+        }
+    }
+}
+
+/// Tokens the heading breadcrumb (see `with_breadcrumb`) adds in front of a chunk.
+fn breadcrumb_tokens(headings: &[String], budget: &Budget) -> usize {
+    if headings.is_empty() {
+        return 0;
+    }
+    budget.count(&with_breadcrumb(headings, "")).saturating_sub(budget.count(""))
+}
+
+/// Splits the content at blank lines. Paragraphs that don't fit in a chunk are split
+/// further: tables by rows (see `split_table`) and text with `hard_split`.
+fn paragraphs(content: &str, budget: &Budget) -> Vec<Piece> {
+    let mut paragraphs = Vec::new();
+    let mut offset = 0;
+    for paragraph in content.split("\n\n") {
+        let start = offset;
+        offset += paragraph.len() + 2;
+        if budget.fits(paragraph) {
+            paragraphs.push(Piece::slice(content, start, start + paragraph.len()));
+            continue;
+        }
+        if !paragraph.lines().any(is_table_line) {
+            paragraphs.extend(hard_split(paragraph, budget).into_iter().map(|p| p.shift(start)));
+            continue;
+        }
+
+        // Runs of table and text lines, as byte ranges of the paragraph
+        let mut run: Option<(usize, usize, bool)> = None;
+        let mut line_start = 0;
+        for line in paragraph.split_inclusive('\n') {
+            let line_end = line_start + line.trim_end_matches(['\n', '\r']).len();
+            let line_is_table = is_table_line(line);
+            run = match run {
+                Some((run_start, run_end, run_is_table)) if run_is_table != line_is_table => {
+                    push_run(&mut paragraphs, &paragraph[run_start..run_end], start + run_start, run_is_table, budget);
+                    Some((line_start, line_end, line_is_table))
+                },
+                Some((run_start, _, run_is_table)) => Some((run_start, line_end, run_is_table)),
+                None => Some((line_start, line_end, line_is_table)),
+            };
+            line_start += line.len();
+        }
+        if let Some((run_start, run_end, run_is_table)) = run {
+            push_run(&mut paragraphs, &paragraph[run_start..run_end], start + run_start, run_is_table, budget);
+        }
+    }
+    paragraphs
+}
+
+fn push_run(paragraphs: &mut Vec<Piece>, run: &str, offset: usize, is_table: bool, budget: &Budget) {
+    let parts = match is_table {
+        true => split_table(run, budget),
+        false => hard_split(run, budget),
+    };
+    paragraphs.extend(parts.into_iter().map(|p| p.shift(offset)));
+}
+
+/// Splits a markdown table into parts of whole rows that fit the budget, each
+/// starting with the header row (and its delimiter row). Rows too long for a
+/// part of their own are split with `hard_split`.
+fn split_table(table: &str, budget: &Budget) -> Vec<Piece> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    for line in table.split_inclusive('\n') {
+        lines.push((offset, offset + line.trim_end_matches(['\n', '\r']).len()));
+        offset += line.len();
+    }
+    let texts: Vec<&str> = lines.iter().map(|(start, end)| &table[*start..*end]).collect();
+    let header_len = table_header_len(&texts);
+    let header_end = match header_len {
+        0 => 0,
+        _ => lines[header_len - 1].1,
+    };
+    let header_tokens = budget.count(&table[..header_end]);
+
+    let mut parts = Vec::new();
+    let mut rows: Option<(usize, usize)> = None;
+    let mut token_count = header_tokens;
+    for &(start, end) in &lines[header_len..] {
+        let row_tokens = budget.count(&table[start..end]);
+        if let Some(full) = rows.filter(|_| token_count + row_tokens > budget.max_tokens) {
+            parts.push(table_part(table, header_end, full, parts.is_empty()));
+            rows = None;
+            token_count = header_tokens;
+        }
+        rows = Some((rows.map_or(start, |(rows_start, _)| rows_start), end));
+        token_count += row_tokens;
+    }
+    if let Some(rows) = rows {
+        parts.push(table_part(table, header_end, rows, parts.is_empty()));
+    }
+    if parts.is_empty() {
+        parts.push(Piece::slice(table, 0, header_end));
+    }
+    parts.into_iter().flat_map(|part| hard_split_piece(part, budget)).collect()
+}
+
+/// The header followed by the rows in `start..end`. The first part is a slice of the
+/// table, later ones repeat the header.
+fn table_part(table: &str, header_end: usize, (start, end): (usize, usize), first: bool) -> Piece {
+    match first {
+        true => Piece::slice(table, 0, end),
+        false => Piece {
+            text: format!("{}\n{}", &table[..header_end], &table[start..end]),
+            start,
+            end,
+        },
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Boundary {
+    Sentence,
+    Clause,
+    Token,
+}
+
+/// Breaks text that doesn't fit the budget at sentence (and line), then clause, then
+/// token boundaries and packs the pieces back together up to the budget. The parts
+/// are slices of the text. Every forced split is logged.
+fn hard_split(text: &str, budget: &Budget) -> Vec<Piece> {
+    let token_count = budget.count(text);
+    if token_count <= budget.max_tokens {
+        return vec![Piece::slice(text, 0, text.len())];
+    }
+
+    let mut deepest = Boundary::Sentence;
+    let ranges = split_ranges(text, (0, text.len()), Boundary::Sentence, budget, &mut deepest);
+    println!(
+        "Forced split of a {} token paragraph in {} into {} parts at {:?} boundaries",
+        token_count,
+        budget.source,
+        ranges.len(),
+        deepest
+    );
+    ranges.into_iter().map(|(start, end)| Piece::slice(text, start, end)).collect()
+}
+
+/// `hard_split` for pieces whose text may start with a repeated table header. The
+/// ranges of the parts are mapped back to the content the piece covers.
+fn hard_split_piece(piece: Piece, budget: &Budget) -> Vec<Piece> {
+    // Bytes of the text that aren't in the range, i.e. the repeated header
+    let prefix = piece.text.len() - (piece.end - piece.start);
+    hard_split(&piece.text, budget)
+        .into_iter()
+        .map(|part| Piece {
+            start: piece.start + part.start.saturating_sub(prefix),
+            end: piece.start + part.end.saturating_sub(prefix),
+            text: part.text,
+        })
+        .collect()
+}
+
+fn split_ranges(text: &str, range: (usize, usize), boundary: Boundary, budget: &Budget, deepest: &mut Boundary) -> Vec<(usize, usize)> {
+    *deepest = (*deepest).max(boundary);
+    let pieces = match boundary {
+        Boundary::Sentence => sentence_ranges(text, range, budget.language),
+        Boundary::Clause => boundary_ranges(text, range, &CLAUSE_MARKS),
+        Boundary::Token => return token_ranges(text, range, budget),
+    };
+    let finer = match boundary {
+        Boundary::Sentence => Boundary::Clause,
+        _ => Boundary::Token,
+    };
+
+    let mut parts = Vec::new();
+    let mut current: Option<(usize, usize)> = None;
+    for (start, end) in pieces {
+        let pieces = match budget.fits(&text[start..end]) {
+            true => vec![(start, end)],
+            false => split_ranges(text, (start, end), finer, budget, deepest),
+        };
+        for (start, end) in pieces {
+            current = match current {
+                Some((current_start, _)) if budget.fits(&text[current_start..end]) => Some((current_start, end)),
+                Some(full) => {
+                    parts.push(full);
+                    Some((start, end))
+                },
+                None => Some((start, end)),
+            };
+        }
+    }
+    parts.extend(current);
+    parts
+}
+
+/// Splits the range after every punctuation mark in `marks` that is followed by
+/// whitespace, and at line breaks. The pieces are trimmed.
+fn boundary_ranges(text: &str, (start, end): (usize, usize), marks: &[char]) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut piece_start = start;
+    let mut chars = text[start..end].char_indices().peekable();
+    while let Some((offset, c)) = chars.next() {
+        let next_is_space = chars.peek().is_some_and(|(_, next)| next.is_whitespace());
+        let cut = match c {
+            '\n' => start + offset,
+            c if marks.contains(&c) && next_is_space => start + offset + c.len_utf8(),
+            _ => continue,
+        };
+        ranges.extend(trim_range(text, piece_start, cut));
+        piece_start = cut;
+    }
+    ranges.extend(trim_range(text, piece_start, end));
+    ranges
+}
+
+/// Cuts the range into windows of as many tokens as fit the budget.
+fn token_ranges(text: &str, (start, end): (usize, usize), budget: &Budget) -> Vec<(usize, usize)> {
+    let slice = &text[start..end];
+    let encoding = match budget.tokenizer.encode(slice, false) {
+        Ok(e) => e,
+        Err(e) => panic!("Error tokenizing: {}", e),
+    };
+    // Leave room for the special tokens `Budget::count` includes
+    let window = budget.max_tokens.saturating_sub(budget.count("")).max(1);
+
+    let mut ranges = Vec::new();
+    let mut piece_start = 0;
+    for token in (window..encoding.len()).step_by(window) {
+        let mut cut = encoding.get_offsets()[token].0.min(slice.len());
+        while !slice.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        if cut > piece_start {
+            ranges.extend(trim_range(text, start + piece_start, start + cut));
+            piece_start = cut;
+        }
+    }
+    ranges.extend(trim_range(text, start + piece_start, end));
+    ranges
+}
+
+/// The range without surrounding whitespace, `None` if nothing is left.
+fn trim_range(text: &str, start: usize, end: usize) -> Option<(usize, usize)> {
+    let piece = &text[start..end];
+    let trimmed = piece.trim_start();
+    let start = start + piece.len() - trimmed.len();
+    let end = start + trimmed.trim_end().len();
+    (end > start).then_some((start, end))
+}
+
+fn table_header_len(lines: &[&str]) -> usize {
+    match lines.get(1) {
+        Some(line) if is_delimiter_row(line) => 2,
+        _ => 1.min(lines.len()),
+    }
+}
+
+
+fn is_table(paragraph: &str) -> bool {
+    paragraph.lines().next().is_some_and(is_table_line) && paragraph.lines().all(is_table_line)
+}
+
+fn is_table_line(line: &str) -> bool {
+    line.trim_start().starts_with('|')
+}
+
+fn is_delimiter_row(line: &str) -> bool {
+    let line = line.trim();
+    line.contains('-') && line.chars().all(|c| matches!(c, '|' | '-' | ':' | ' '))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+
+    use tokenizers::{models::wordlevel::WordLevel, pre_tokenizers::whitespace::Whitespace, Tokenizer};
+
+    use crate::docs::chunk::ChunkSpan;
+
+    use super::*;
+
+    /// Counts words and punctuation runs as tokens.
+    pub(crate) fn word_tokenizer() -> Tokenizer {
+        let model = WordLevel::builder()
+            .vocab(HashMap::from([("[UNK]".to_string(), 0)]))
+            .unk_token("[UNK]".to_string())
+            .build()
+            .unwrap();
+        let mut tokenizer = Tokenizer::new(model);
+        tokenizer.with_pre_tokenizer(Whitespace {});
+        tokenizer
+    }
+
+    fn overlap_settings() -> SplitterConfig {
+        SplitterConfig {
+            overlap_tokens: 6,
+            overlap_paragraphs: 1,
+            strategy: SplitStrategy::Overlapping,
+            ..Default::default()
+        }
+    }
+
+    fn assert_slices(content: &str, chunks: &[SectionChunk]) {
+        for chunk in chunks {
+            assert_eq!(&content[chunk.range.0..chunk.range.1], chunk.text);
+        }
+    }
+
+    #[test]
+    fn packed_paragraphs_are_slices_of_the_content() {
+        let tokenizer = word_tokenizer();
+        let budget = Budget::new(&tokenizer, 8, "test");
+        let content = "Prvi odstavek je kratek.\n\nDrugi odstavek je tudi kratek.\n\nTretji.";
+        let chunks = pack_paragraphs(content, &budget, None);
+        assert_eq!(chunks.len(), 2);
+        assert_slices(content, &chunks);
+        assert_eq!(chunks[1].text, "Drugi odstavek je tudi kratek.\n\nTretji.");
+    }
+
+    #[test]
+    fn hard_split_parts_keep_their_ranges() {
+        let tokenizer = word_tokenizer();
+        let budget = Budget::new(&tokenizer, 6, "test");
+        let content = "Uvod.\n\nŠtudent vpiše predmet. Čez teden dobi potrdilo. Žig ni potreben. Konec.";
+        let chunks = pack_paragraphs(content, &budget, None);
+        assert!(chunks.len() > 2);
+        assert_slices(content, &chunks);
+        for chunk in &chunks {
+            assert!(budget.fits(&chunk.text));
+            // Ranges end on char boundaries, so the span doesn't panic
+            ChunkSpan::new(content, chunk.range.0, chunk.range.1);
+        }
+    }
+
+    #[test]
+    fn overlapping_chunks_repeat_sentences_as_slices() {
+        let tokenizer = word_tokenizer();
+        let budget = Budget::new(&tokenizer, 14, "test");
+        let content = "Prvi stavek je dolg in vsebuje veliko besed. Drugi je kratek.\n\nNov odstavek z nekaj besedami.";
+        let chunks = pack_paragraphs(content, &budget, Some(&overlap_settings()));
+        assert_slices(content, &chunks);
+        assert!(chunks[1].text.starts_with("Drugi je kratek."));
+        assert!(chunks[1].range.0 < chunks[0].range.1);
+    }
+
+    #[test]
+    fn table_parts_repeat_the_header_and_cover_their_rows() {
+        let tokenizer = word_tokenizer();
+        let budget = Budget::new(&tokenizer, 20, "test");
+        let content = "| a | b |\r\n|---|---|\r\n| 1 | 2 |\r\n| 3 | 4 |\r\n| 5 | 6 |";
+        let chunks = pack_paragraphs(content, &budget, None);
+        assert!(chunks.len() > 1);
+        assert_eq!(&content[chunks[0].range.0..chunks[0].range.1], chunks[0].text);
+        for chunk in &chunks[1..] {
+            assert!(chunk.text.starts_with("| a | b |\r\n|---|---|\n"));
+            assert!(chunk.text.ends_with(&content[chunk.range.0..chunk.range.1]));
+            assert!(content[chunk.range.0..].starts_with("| "));
+        }
+    }
+
+    #[test]
+    fn markdown_chunks_keep_headings_and_ranges() {
+        let tokenizer = word_tokenizer();
+        let budget = Budget::new(&tokenizer, 12, "test");
+        let content = "# Vpis\n\nRoki za vpis.\n\n## Prijava\n\nPrijava poteka prek portala.\n\n# Študij\n\nPredmeti.";
+        let chunks = split_markdown(content, &budget);
+        assert_slices(content, &chunks);
+        assert_eq!(chunks.last().unwrap().headings, vec!["Študij".to_string()]);
+    }
+
+    #[test]
+    fn located_chunks_carry_their_position() {
+        let content = "Prvi odstavek je kratek.\n\nDrugi odstavek je tudi kratek.\n\nTretji.";
+        let document = Doc {
+            id: "doc".to_string(),
+            content: content.to_string(),
+            file_name: "doc.md".to_string(),
+            relative_path: "docs/doc.md".to_string(),
+            pages: vec![],
+            metadata: Default::default(),
+        };
+        let splitter = PromptSplitter {
+            tokenizer: word_tokenizer(),
+            settings: SplitterConfig { chunk_tokens: 8, ..Default::default() },
+        };
+        let located = splitter.split_located(&document);
+        assert_eq!(located.len(), 2);
+        let (text, provenance) = &located[1];
+        assert_eq!(text, "Drugi odstavek je tudi kratek.\n\nTretji.");
+        assert_eq!((provenance.source.as_str(), provenance.index), ("docs/doc.md", 1));
+        assert_eq!((provenance.span.start_line, provenance.span.end_line), (3, 5));
+        assert_eq!(provenance.strategy, SplitStrategy::Prompts);
+    }
+
+    #[test]
+    fn breadcrumbs_prefix_the_heading_path() {
+        let headings = vec!["Vpis".to_string(), "Prijava".to_string()];
+        assert_eq!(with_breadcrumb(&headings, "Besedilo."), "Vpis › Prijava\n\nBesedilo.");
+        assert_eq!(with_breadcrumb(&[], "Besedilo."), "Besedilo.");
+    }
+
+    #[test]
+    fn breadcrumbed_chunks_stay_within_budget() {
+        let tokenizer = word_tokenizer();
+        let budget = Budget::new(&tokenizer, 12, "test");
+        let content = "# Vpis v prvi letnik\n\n## Prijava na razpis\n\nPrijava poteka prek portala eVŠ. Rok je julija. Dokazila pošljete po pošti. Sklep dobite avgusta.\n\nVpis je septembra.";
+        let chunks = split_markdown(content, &budget);
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(budget.fits(&with_breadcrumb(&chunk.headings, &chunk.text)), "{:?}", chunk.text);
+        }
+    }
+}
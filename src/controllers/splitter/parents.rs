@@ -0,0 +1,137 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::ParentConfig, docs::{chunk::{ChunkSpan, ParentChunk, Provenance}, doc::Doc}};
+
+use super::{build_splitter, Splitter};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ParentMode {
+    /// Chunks are stored without a parent
+    #[default]
+    None,
+    /// The parent is the chunk of a second, coarser splitter that overlaps the chunk most
+    Chunks,
+    /// The parent is the chunk with `window` neighbouring chunks on each side
+    Window,
+}
+
+/// Finds the larger parent passage of every embedded chunk (small-to-big retrieval):
+/// the small chunks are embedded, the parent text is stored with them for the answering model.
+pub struct Parents {
+    settings: ParentConfig,
+    splitter: Option<Box<dyn Splitter>>,
+}
+
+impl Parents {
+    pub fn build(settings: &ParentConfig) -> Result<Self> {
+        let splitter = match settings.mode {
+            ParentMode::Chunks => Some(build_splitter(&settings.splitter)?),
+            _ => None,
+        };
+        Ok(Self { settings: settings.clone(), splitter })
+    }
+
+    /// Returns the parent of each chunk. Windows are taken by chunk ordinal, so chunks
+    /// left out (e.g. by the chunk filter) don't shift them.
+    pub fn of(&self, document: &Doc, chunks: &[Provenance]) -> Vec<Option<ParentChunk>> {
+        match (self.settings.mode, &self.splitter) {
+            (ParentMode::Chunks, Some(splitter)) => {
+                let parents = splitter.split_located(document);
+                chunks
+                    .iter()
+                    .map(|chunk| {
+                        parents
+                            .iter()
+                            .map(|(text, provenance)| (overlap(&chunk.span, &provenance.span), text, provenance))
+                            .filter(|(overlap, _, _)| *overlap > 0)
+                            .max_by_key(|(overlap, _, provenance)| (*overlap, std::cmp::Reverse(provenance.index)))
+                            .map(|(_, text, provenance)| ParentChunk {
+                                index: provenance.index,
+                                text: text.clone(),
+                                span: provenance.span.clone(),
+                            })
+                    })
+                    .collect()
+            },
+            (ParentMode::Window, _) => chunks
+                .iter()
+                .map(|chunk| {
+                    let window: Vec<&ChunkSpan> = chunks
+                        .iter()
+                        .filter(|other| other.index.abs_diff(chunk.index) <= self.settings.window)
+                        .map(|other| &other.span)
+                        .collect();
+                    let start = window.iter().map(|s| s.start_byte).min().unwrap_or_default();
+                    let end = window.iter().map(|s| s.end_byte).max().unwrap_or_default();
+                    Some(ParentChunk {
+                        index: chunk.index,
+                        text: document.content[start..end].to_string(),
+                        span: ChunkSpan::new(&document.content, start, end),
+                    })
+                })
+                .collect(),
+            _ => vec![None; chunks.len()],
+        }
+    }
+}
+
+/// Number of bytes two spans share.
+fn overlap(a: &ChunkSpan, b: &ChunkSpan) -> usize {
+    a.end_byte.min(b.end_byte).saturating_sub(a.start_byte.max(b.start_byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{config::SplitterConfig, docs::doc::DocMetadata};
+
+    use super::*;
+
+    fn document(content: &str) -> Doc {
+        Doc {
+            id: "doc".to_string(),
+            content: content.to_string(),
+            file_name: "doc.md".to_string(),
+            relative_path: "doc.md".to_string(),
+            pages: vec![],
+            metadata: DocMetadata::default(),
+        }
+    }
+
+    fn chunk(content: &str, index: usize, text: &str) -> Provenance {
+        let start = content.find(text).unwrap();
+        Provenance {
+            source: "doc.md".to_string(),
+            index,
+            span: ChunkSpan::new(content, start, start + text.len()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn windows_follow_chunk_ordinals() {
+        let content = "Prvi.\n\nDrugi.\n\nTretji.\n\nČetrti.";
+        let parents = Parents {
+            settings: ParentConfig { mode: ParentMode::Window, window: 1, splitter: SplitterConfig::default() },
+            splitter: None,
+        };
+        // The third chunk was dropped
+        let chunks = vec![chunk(content, 0, "Prvi."), chunk(content, 1, "Drugi."), chunk(content, 3, "Četrti.")];
+        let found = parents.of(&document(content), &chunks);
+
+        let first = found[0].as_ref().unwrap();
+        assert_eq!((first.index, first.text.as_str()), (0, "Prvi.\n\nDrugi."));
+        let second = found[1].as_ref().unwrap();
+        assert_eq!((second.index, second.text.as_str()), (1, "Prvi.\n\nDrugi."));
+        let last = found[2].as_ref().unwrap();
+        assert_eq!((last.index, last.text.as_str()), (3, "Četrti."));
+    }
+
+    #[test]
+    fn no_parents_without_a_mode() {
+        let parents = Parents { settings: ParentConfig::default(), splitter: None };
+        let content = "Prvi.";
+        assert_eq!(parents.of(&document(content), &[chunk(content, 0, "Prvi.")]), vec![None]);
+    }
+}
@@ -0,0 +1,256 @@
+use anyhow::Result;
+use candle_core::Device;
+use candle_transformers::models::bert::BertModel;
+use tokenizers::Tokenizer;
+
+use crate::{config::SplitterConfig, docs::doc::Doc, llm::{embedding_model::embedd_with, loader::{embedding_gpu, load_bert_model, load_embedding_max_tokens}}};
+
+use super::{is_table, join_pieces, paragraphs, sentences::sentence_ranges, Budget, Piece, SectionChunk, SemanticUnit, SplitStrategy, Splitter};
+
+/// Places chunk boundaries where the embeddings of neighbouring units (sentences or
+/// paragraphs) are least similar. Distances at or above the `breakpoint_percentile`
+/// of a document's distances start a new chunk, once the chunk has `min_chunk_tokens`.
+/// Chunks never exceed `chunk_tokens`.
+///
+/// The embedding model is loaded on `embedding.device`, next to the LLMs of the command
+/// (about 1.3 GB for a BGE large model). The worker threads of a batch share it, as
+/// `forward` takes `&self` and the weights are reference counted tensors (`Splitter`
+/// requires `Sync`, so the compiler checks this).
+pub struct SemanticSplitter {
+    tokenizer: Tokenizer,
+    settings: SplitterConfig,
+    model: BertModel,
+    model_tokenizer: Tokenizer,
+    device: Device,
+    model_max_tokens: usize,
+}
+
+/// A sentence or paragraph of the document.
+struct Unit {
+    piece: Piece,
+    token_count: usize,
+}
+
+impl SemanticSplitter {
+    pub fn new(tokenizer: Tokenizer, settings: SplitterConfig) -> Result<Self> {
+        let (model, model_tokenizer, device) = load_bert_model(embedding_gpu())?;
+        Ok(Self {
+            tokenizer,
+            settings,
+            model,
+            model_tokenizer,
+            device,
+            model_max_tokens: load_embedding_max_tokens()?,
+        })
+    }
+
+    /// Cosine distances between each unit and the previous one (0 for the first unit).
+    fn distances(&self, units: &[Unit], source: &str) -> Vec<f32> {
+        let mut embeddings = Vec::with_capacity(units.len());
+        for unit in units {
+            match embedd_with(&self.model, &self.model_tokenizer, &self.device, &unit.piece.text, self.model_max_tokens) {
+                Ok(e) => embeddings.push(e),
+                Err(e) => {
+                    println!("Can't embed a passage of {}, splitting it by size only: {:#?}", source, e);
+                    return vec![0.; units.len()];
+                },
+            }
+        }
+
+        let mut distances = vec![0.];
+        for pair in embeddings.windows(2) {
+            // Embeddings are L2 normalized
+            let similarity: f32 = pair[0].iter().zip(&pair[1]).map(|(a, b)| a * b).sum();
+            distances.push(1. - similarity);
+        }
+        distances.truncate(units.len());
+        distances
+    }
+}
+
+impl Splitter for SemanticSplitter {
+    fn strategy(&self) -> SplitStrategy {
+        self.settings.strategy
+    }
+
+    fn split(&self, document: &Doc) -> Vec<SectionChunk> {
+        let budget = Budget::for_document(&self.tokenizer, self.settings.chunk_tokens, document);
+        let units = units(&document.content, &budget, self.settings.semantic_unit);
+        let distances = self.distances(&units, &document.file_name);
+        pack_units(&document.content, &units, &distances, &self.settings)
+    }
+}
+
+/// Estimates the chunks of `SemanticSplitter` without the embedding model, for `plan`. Of
+/// the distances between units, `100 - breakpoint_percentile` % reach the percentile, so
+/// that share of the boundaries is taken as topic shifts, spread evenly over the document.
+pub struct SemanticEstimate {
+    pub(super) tokenizer: Tokenizer,
+    pub(super) settings: SplitterConfig,
+}
+
+impl Splitter for SemanticEstimate {
+    fn strategy(&self) -> SplitStrategy {
+        self.settings.strategy
+    }
+
+    fn split(&self, document: &Doc) -> Vec<SectionChunk> {
+        let budget = Budget::for_document(&self.tokenizer, self.settings.chunk_tokens, document);
+        let units = units(&document.content, &budget, self.settings.semantic_unit);
+        let distances = estimated_distances(units.len(), self.settings.breakpoint_percentile);
+        pack_units(&document.content, &units, &distances, &self.settings)
+    }
+}
+
+/// Distances of 1 at evenly spread topic shifts, 0 (never a breakpoint) elsewhere.
+fn estimated_distances(count: usize, percentile: f64) -> Vec<f32> {
+    let share = (100. - percentile.clamp(0., 100.)) / 100.;
+    let mut distances = vec![0.];
+    let mut shifts = 0.;
+    for _ in 1..count {
+        shifts += share;
+        if shifts >= 1. {
+            shifts -= 1.;
+            distances.push(1.);
+        } else {
+            distances.push(0.);
+        }
+    }
+    distances.truncate(count);
+    distances
+}
+
+fn units(content: &str, budget: &Budget, semantic_unit: SemanticUnit) -> Vec<Unit> {
+    let mut units = Vec::new();
+    for paragraph in paragraphs(content, budget) {
+        // Tables (and their parts repeating the header) stay whole
+        if semantic_unit == SemanticUnit::Paragraph || is_table(&paragraph.text) {
+            units.push(Unit { token_count: budget.count(&paragraph.text), piece: paragraph });
+            continue;
+        }
+        for (start, end) in sentence_ranges(&paragraph.text, (0, paragraph.text.len()), budget.language) {
+            let piece = Piece::slice(&paragraph.text, start, end).shift(paragraph.start);
+            units.push(Unit { token_count: budget.count(&piece.text), piece });
+        }
+    }
+    units
+}
+
+/// Packs the units into chunks, starting a new one at a topic shift or when the chunk is full.
+fn pack_units(content: &str, units: &[Unit], distances: &[f32], settings: &SplitterConfig) -> Vec<SectionChunk> {
+    let threshold = percentile(&distances[distances.len().min(1)..], settings.breakpoint_percentile);
+
+    let mut chunks = Vec::new();
+    let mut current: Vec<&Piece> = Vec::new();
+    let mut current_token_count = 0;
+    for (unit, &distance) in units.iter().zip(distances) {
+        let topic_shift = distance > 0. && distance >= threshold && current_token_count >= settings.min_chunk_tokens;
+        let full = current_token_count + unit.token_count > settings.chunk_tokens;
+        if !current.is_empty() && (topic_shift || full) {
+            chunks.push(join_pieces(content, current.drain(..), vec![]));
+            current_token_count = 0;
+        }
+        current.push(&unit.piece);
+        current_token_count += unit.token_count;
+    }
+    if !current.is_empty() {
+        chunks.push(join_pieces(content, current, vec![]));
+    }
+
+    chunks
+}
+
+/// Nearest-rank percentile, `f32::MAX` for no values (no breakpoints).
+fn percentile(values: &[f32], percentile: f64) -> f32 {
+    if values.is_empty() {
+        return f32::MAX;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let rank = (percentile.clamp(0., 100.) / 100. * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::word_tokenizer;
+    use super::*;
+
+    const CONTENT: &str = "Vpis poteka julija. Roki so objavljeni.\n\nŠtipendije podeljuje sklad.\n\nŠtudentski dom je blizu.";
+
+    fn settings(chunk_tokens: usize) -> SplitterConfig {
+        SplitterConfig {
+            strategy: SplitStrategy::Semantic,
+            chunk_tokens,
+            min_chunk_tokens: 0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sentence_units_are_slices_of_the_content() {
+        let tokenizer = word_tokenizer();
+        let budget = Budget::new(&tokenizer, 100, "test");
+        let units = units(CONTENT, &budget, SemanticUnit::Sentence);
+        let texts: Vec<&str> = units.iter().map(|u| u.piece.text.as_str()).collect();
+        assert_eq!(texts, vec!["Vpis poteka julija.", "Roki so objavljeni.", "Štipendije podeljuje sklad.", "Študentski dom je blizu."]);
+        for unit in &units {
+            assert_eq!(&CONTENT[unit.piece.start..unit.piece.end], unit.piece.text);
+        }
+    }
+
+    #[test]
+    fn chunks_break_at_topic_shifts() {
+        let tokenizer = word_tokenizer();
+        let budget = Budget::new(&tokenizer, 100, "test");
+        let units = units(CONTENT, &budget, SemanticUnit::Paragraph);
+        let chunks = pack_units(CONTENT, &units, &[0., 0.1, 0.9], &settings(100));
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].text, "Vpis poteka julija. Roki so objavljeni.\n\nŠtipendije podeljuje sklad.");
+        assert_eq!(chunks[1].text, "Študentski dom je blizu.");
+        for chunk in &chunks {
+            assert_eq!(&CONTENT[chunk.range.0..chunk.range.1], chunk.text);
+        }
+    }
+
+    #[test]
+    fn full_chunks_break_without_a_topic_shift() {
+        let tokenizer = word_tokenizer();
+        let budget = Budget::new(&tokenizer, 100, "test");
+        let units = units(CONTENT, &budget, SemanticUnit::Paragraph);
+        // Without an embedding every distance is 0, so only the size splits
+        let chunks = pack_units(CONTENT, &units, &[0.; 3], &settings(8));
+        assert_eq!(chunks.len(), 3);
+    }
+
+    #[test]
+    fn estimates_shift_topics_at_the_percentile_share() {
+        assert_eq!(estimated_distances(5, 75.), vec![0., 0., 0., 0., 1.]);
+        assert!(estimated_distances(10, 100.).iter().all(|d| *d == 0.));
+
+        let content = (1..=20).map(|i| format!("Odstavek {}.", i)).collect::<Vec<_>>().join("\n\n");
+        let document = Doc {
+            id: "doc".to_string(),
+            content,
+            file_name: "doc.md".to_string(),
+            relative_path: "doc.md".to_string(),
+            pages: vec![],
+            metadata: Default::default(),
+        };
+        let estimate = SemanticEstimate {
+            tokenizer: word_tokenizer(),
+            settings: SplitterConfig { breakpoint_percentile: 80., ..settings(100) },
+        };
+        // A shift after every fifth paragraph
+        let chunks = estimate.split(&document);
+        assert_eq!(chunks.len(), 4);
+        assert!(chunks[0].text.ends_with("Odstavek 5."));
+    }
+
+    #[test]
+    fn percentile_takes_the_nearest_rank() {
+        assert_eq!(percentile(&[0.3, 0.1, 0.2], 50.), 0.2);
+        assert_eq!(percentile(&[0.3, 0.1, 0.2], 90.), 0.3);
+        assert_eq!(percentile(&[], 90.), f32::MAX);
+    }
+}
@@ -0,0 +1,168 @@
+const SENTENCE_MARKS: [char; 4] = ['.', '!', '?', '…'];
+// May follow a sentence mark, e.g. `(see below.)` or `"Done."`
+const CLOSING_MARKS: [char; 7] = ['"', '\'', ')', ']', '”', '’', '»'];
+
+const SL_ABBREVIATIONS: [&str; 52] = [
+    "npr.", "t.i.", "t. i.", "tj.", "oz.", "itd.", "ipd.", "idr.", "ibid.", "prim.", "gl.", "op.", "nav.",
+    "št.", "str.", "čl.", "odst.", "tč.", "al.", "pogl.", "tab.", "sl.", "tel.", "ul.", "c.", "sv.",
+    "prof.", "dr.", "doc.", "izr.", "red.", "mag.", "dipl.", "univ.", "ing.", "asist.", "ga.", "g.", "gdč.",
+    "jan.", "feb.", "avg.", "sept.", "okt.", "nov.", "dec.", "pribl.", "cca.", "mio.", "mrd.",
+    "d.o.o.", "s.p.",
+];
+
+const EN_ABBREVIATIONS: [&str; 43] = [
+    "e.g.", "i.e.", "etc.", "vs.", "cf.", "al.", "approx.", "incl.", "excl.", "no.", "nos.", "vol.", "fig.",
+    "p.", "pp.", "ed.", "eds.", "ch.", "sec.", "dept.", "univ.", "mr.", "mrs.", "ms.", "dr.", "prof.",
+    "assoc.", "asst.", "jr.", "sr.", "st.", "jan.", "feb.", "mar.", "apr.", "jun.", "jul.", "aug.", "sep.",
+    "sept.", "oct.", "inc.", "ltd.",
+];
+
+// Abbreviations that often end a sentence, so they do when followed by a capital letter
+const TERMINAL_ABBREVIATIONS: [&str; 8] = ["itd.", "ipd.", "idr.", "etc.", "inc.", "ltd.", "d.o.o.", "s.p."];
+
+/// Splits `range` of the text into sentences and returns their trimmed byte ranges.
+///
+/// A `.`, `!`, `?` or `…` followed by whitespace ends a sentence, as does a line break, except:
+/// - after an abbreviation of the language (Slovene and English ones if `language` is not
+///   `sl` or `en`), e.g. `npr.`, `t. i.`, `prof.` or `e.g.`
+/// - after numbers followed by a lowercase word or another number, e.g. `1. letnik` or `1. 10. 2024`
+/// - after initials, e.g. `J. Novak`
+/// - before a lowercase word
+pub fn sentence_ranges(text: &str, (start, end): (usize, usize), language: Option<&str>) -> Vec<(usize, usize)> {
+    let slice = &text[start..end];
+    let mut ranges = Vec::new();
+    let mut sentence_start = 0;
+    let mut chars = slice.char_indices().peekable();
+
+    while let Some((offset, c)) = chars.next() {
+        let cut = if c == '\n' {
+            offset
+        } else if SENTENCE_MARKS.contains(&c) {
+            let mut mark_end = offset + c.len_utf8();
+            while let Some((next_offset, next)) = chars.peek().copied() {
+                if !CLOSING_MARKS.contains(&next) && !SENTENCE_MARKS.contains(&next) {
+                    break;
+                }
+                mark_end = next_offset + next.len_utf8();
+                chars.next();
+            }
+            if !slice[mark_end..].starts_with(char::is_whitespace) || !ends_sentence(slice, offset, mark_end, c, language) {
+                continue;
+            }
+            mark_end
+        } else {
+            continue;
+        };
+        ranges.extend(trim(text, start + sentence_start, start + cut));
+        sentence_start = cut;
+    }
+    ranges.extend(trim(text, start + sentence_start, end));
+    ranges
+}
+
+/// Decides whether the mark at `mark_start` (ending, with closing marks, at `mark_end`) ends a sentence.
+fn ends_sentence(text: &str, mark_start: usize, mark_end: usize, mark: char, language: Option<&str>) -> bool {
+    let next = text[mark_end..].trim_start().chars().next();
+    let next_is_lowercase = next.is_some_and(|n| n.is_lowercase());
+    if next_is_lowercase {
+        return false;
+    }
+    if mark != '.' {
+        return true;
+    }
+
+    let word = last_word(&text[..mark_start]);
+    if word.chars().all(|c| c.is_ascii_digit()) && !word.is_empty() {
+        // Dates and ordinals like `1. 10. 2024`, a number followed by a capital ends a sentence
+        return !next.is_some_and(|n| n.is_ascii_digit());
+    }
+    let mut chars = word.chars();
+    if let (Some(initial), None) = (chars.next(), chars.next()) {
+        if initial.is_uppercase() {
+            return false;
+        }
+    }
+
+    let abbreviation = format!("{}.", word.to_lowercase());
+    // Two word abbreviations like `t. i.`
+    let previous = last_word(text[..mark_start - word.len()].trim_end()).trim_end_matches('.');
+    let two_words = format!("{}. {}", previous.to_lowercase(), abbreviation);
+    let is_abbreviation = |a: &&str| *a == abbreviation || *a == two_words;
+    let known = match language {
+        Some("sl") => SL_ABBREVIATIONS.iter().any(is_abbreviation),
+        Some("en") => EN_ABBREVIATIONS.iter().any(is_abbreviation),
+        _ => SL_ABBREVIATIONS.iter().chain(EN_ABBREVIATIONS.iter()).any(is_abbreviation),
+    };
+    if !known {
+        return true;
+    }
+    TERMINAL_ABBREVIATIONS.contains(&abbreviation.as_str()) && next.is_some_and(|n| n.is_uppercase())
+}
+
+/// The word right before the end of the text, including inner dots (`t.i`, `d.o.o`).
+fn last_word(text: &str) -> &str {
+    let start = text
+        .char_indices()
+        .rev()
+        .find(|(_, c)| !(c.is_alphanumeric() || *c == '.'))
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    text[start..].trim_start_matches('.')
+}
+
+fn trim(text: &str, start: usize, end: usize) -> Option<(usize, usize)> {
+    let piece = &text[start..end];
+    let trimmed = piece.trim_start();
+    let start = start + piece.len() - trimmed.len();
+    let end = start + trimmed.trim_end().len();
+    (end > start).then_some((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sentences<'a>(text: &'a str, language: Option<&str>) -> Vec<&'a str> {
+        sentence_ranges(text, (0, text.len()), language)
+            .into_iter()
+            .map(|(start, end)| &text[start..end])
+            .collect()
+    }
+
+    #[test]
+    fn spaced_abbreviations_dont_end_sentences() {
+        assert_eq!(
+            sentences("To je t. i. Erasmus program. Prijave so odprte.", Some("sl")),
+            vec!["To je t. i. Erasmus program.", "Prijave so odprte."]
+        );
+        assert_eq!(
+            sentences("Velja t.i. Bolonjski sistem. Drugo.", None),
+            vec!["Velja t.i. Bolonjski sistem.", "Drugo."]
+        );
+    }
+
+    #[test]
+    fn abbreviations_titles_and_dates() {
+        assert_eq!(
+            sentences("Predava prof. dr. J. Novak. Rok je 1. 10. 2024. Npr. Erasmus, itd. Konec.", Some("sl")),
+            vec!["Predava prof. dr. J. Novak.", "Rok je 1. 10. 2024.", "Npr. Erasmus, itd.", "Konec."]
+        );
+        assert_eq!(
+            sentences("Bring documents, e.g. Your ID. Then enrol.", Some("en")),
+            vec!["Bring documents, e.g. Your ID.", "Then enrol."]
+        );
+    }
+
+    #[test]
+    fn lines_and_closing_marks_end_sentences() {
+        assert_eq!(
+            sentences("Prvi (glej spodaj.) Drugi?\nTretji", None),
+            vec!["Prvi (glej spodaj.)", "Drugi?", "Tretji"]
+        );
+    }
+
+    #[test]
+    fn ranges_are_relative_to_the_whole_text() {
+        let text = "Uvod. Prvi stavek. Drugi stavek.";
+        assert_eq!(sentence_ranges(text, (6, text.len()), None), vec![(6, 18), (19, 32)]);
+    }
+}
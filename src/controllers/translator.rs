@@ -1,18 +1,31 @@
-use std::{cmp::min, sync::{Arc, Mutex}};
+use std::{cmp::min, collections::{HashMap, HashSet}, path::Path, sync::{Arc, Mutex}};
 use candle_core::Device;
 use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use crate::{
-    config::{PAR_CHUNK_SIZE, TRANSLATION_MODEL, TRANSLATION_TOKENIZER, TRANSLATOR_PROGRESS_FILE, TRANSLATOR_SYSTEM_MSG}, 
-    docs::{doc::Doc, embedded_doc, saver::{save_raw, save_to_json}}, 
+    config::config, 
+    docs::{chunk::ProcessedDocumentChunk, doc::{Doc, DocMetadata}, embedded_doc, language::normalize_language, loader::DocStream, manifest::{prepare_incremental, Manifest, ManifestOutput}, saver::{save_raw, save_to_json}}, 
     llm::{model::load_model, prompt::{prompt_model, Prompt}, tokenizer::load_tokenizer}, 
     util::{get_progress_bar, load_progress, save_progress, Progress}
 };
-use super::splitter::{merge_parsed_documents, split_to_prompts};
+use super::splitter::{build_splitter, merge_parsed_documents};
 
-pub type ProcessedDocumentChunk = (String, String, bool);
+// Outputs written next to each translated file
+pub const TRANSLATED_SUFFIX: &str = "_translated.md";
+pub const RECORDS_SUFFIX: &str = ".jsonl";
 
-pub fn translate(mut docs: Vec<Doc>) {
-    println!("Docs to translate: {}", docs.len());
+pub fn translate(mut docs: DocStream) {
+    let settings = &config().translate;
+    let incremental = config().progress.incremental;
+    let mut manifest = Manifest::default();
+    let mut hashes = HashMap::new();
+    if incremental {
+        manifest = Manifest::load(&settings.manifest_file);
+        (docs, hashes, _) = match prepare_incremental(docs, &mut manifest) {
+            Ok(r) => r,
+            Err(e) => panic!("Can't compare docs against the manifest: {:#?}", e),
+        };
+    }
+    println!("Docs to translate: {}", docs.remaining_files());
     let device1 = match Device::new_cuda(0) {
         Ok(cuda) => cuda,
         Err(e) => {
@@ -29,39 +42,62 @@ pub fn translate(mut docs: Vec<Doc>) {
         },
     };
 
-    let tokenizer = match load_tokenizer(TRANSLATION_TOKENIZER) {
+    let tokenizer = match load_tokenizer(&settings.tokenizer) {
         Ok(t) => t,
         Err(e) => panic!("Can't load tokenizer: {:#?}", e),
     };
 
-    let model1 = match load_model(TRANSLATION_MODEL, &device1) { 
+    let splitter = match build_splitter(&settings.splitter) {
+        Ok(s) => s,
+        Err(e) => panic!("Can't build splitter: {:#?}", e),
+    };
+
+    let model1 = match load_model(&settings.model, &device1) { 
         Ok(m) => Arc::new(Mutex::new(m)),
         Err(e) => panic!("Can't load model: {:#?}", e),
     };
 
-    let model2 = match load_model(TRANSLATION_MODEL, &device2) { 
+    let model2 = match load_model(&settings.model, &device2) { 
         Ok(m) => Arc::new(Mutex::new(m)),
         Err(e) => panic!("Can't load model: {:#?}", e),
     };
 
-    let mut progress: Progress = load_progress(TRANSLATOR_PROGRESS_FILE);
-
+    let mut progress: Progress = load_progress(&settings.progress_file);
+    // The manifest already leaves out files finished in earlier runs
+    if incremental {
+        progress.batches_done = 0;
+    }
     
     let to_process = if let Some(bound) = progress.files_to_process {
-        min(bound, docs.len())
+        min(bound, docs.remaining_files())
     } else {
-        docs.len()
+        docs.remaining_files()
     };
 
     let mut done = progress.batches_done * progress.par_chunk_size;
     let progress_bar = get_progress_bar(to_process, 0);
     progress_bar.inc(done); 
     
-    docs.drain(0..(done as usize));
+    let mut docs = docs.skip_docs(done as usize);
+    let mut failed = HashSet::new();
 
-    for batch in docs.chunks(progress.par_chunk_size as usize) {
+    loop {
+        let batch = docs.next_batch(progress.par_chunk_size as usize);
+        if batch.is_empty() {
+            break;
+        }
 
-        let results: Vec<(String, Vec<ProcessedDocumentChunk>)> = batch.par_iter().enumerate().map(|(index, document)| {
+        let results: Vec<(&Doc, Vec<ProcessedDocumentChunk>)> = batch.par_iter().enumerate().map(|(index, document)| {
+
+            // Documents already in the target language are passed through as they are
+            if !needs_translation(document) {
+                println!("Skipping {}, already in {}", document.file_name, settings.target_language);
+                let responses = splitter.split_located(document)
+                    .into_iter()
+                    .map(|(passage, provenance)| ProcessedDocumentChunk { input: passage.clone(), output: passage, success: true, provenance })
+                    .collect();
+                return (document, responses);
+            }
 
             // Select the appropriate model and device based on the index
             let (mut model, device) = match index % 2 {
@@ -70,56 +106,160 @@ pub fn translate(mut docs: Vec<Doc>) {
             };
 
             let mut responses: Vec<ProcessedDocumentChunk> = vec![]; 
-            let prompts = split_to_prompts(document);
+            let prompts = splitter.split_located(document);
             let prompts_len = prompts.len();
             let doc_progress = get_progress_bar(prompts_len, 1);
             
-            for prompt_string in prompts {
+            for (prompt_string, provenance) in prompts {
                 // Process the prompt with the selected model and device
                 let question = prompt_string.clone();
-                let prompt = Prompt::One(
-                    TRANSLATOR_SYSTEM_MSG.to_string(),
-                    prompt_string
-                );
-                match prompt_model(&mut *model, &tokenizer, prompt, device) {
-                    Ok(out) => responses.push((question, out, true)),
-                    Err(e) => responses.push((question, e.to_string(), false)),
+                let prompt = translation_prompt(prompt_string);
+                let (output, success) = match prompt_model(&mut *model, &tokenizer, prompt, device) {
+                    Ok(out) => (out, true),
+                    Err(e) => (e.to_string(), false),
                 };
+                responses.push(ProcessedDocumentChunk { input: question, output, success, provenance });
                 doc_progress.inc(1);
             }
 
-            (document.file_name.clone(), responses)
+            (document, responses)
 
         }).collect();
 
-        progress_bar.inc(PAR_CHUNK_SIZE); 
+        progress_bar.inc(progress.par_chunk_size); 
         done += progress.par_chunk_size;
 
         progress.batches_done += 1;
 
 
-        for (file, records) in &results {
-            if let Err(e) = save_to_json(&records, &format!("{file}.jsonl")) {
-                println!("Failed saving records: {:#?}", e)
+        for (document, records) in results {
+            let file = &document.file_name;
+            let relative_path = &document.relative_path;
+            let mut succeeded = records.iter().all(|r| r.success);
+            if let Err(e) = save_to_json(&records, &format!("{file}{RECORDS_SUFFIX}")) {
+                println!("Failed saving records: {:#?}", e);
+                succeeded = false;
             };
-        }
-
-        for (file, records) in results {
-            let tranlsated_content = merge_parsed_documents(records);
-            if let Err(e) = save_raw(tranlsated_content, format!("{file}_translated.md")) {
-                println!("Failed saving records: {:#?}", e)
+            let tranlsated_content = with_target_language(document, merge_parsed_documents(records));
+            if let Err(e) = save_raw(tranlsated_content, format!("{file}{TRANSLATED_SUFFIX}")) {
+                println!("Failed saving records: {:#?}", e);
+                succeeded = false;
+            };
+            let hash = match hashes.get(relative_path) {
+                Some(h) => h,
+                None => continue,
             };
-        } 
+            // Files with failed passages or outputs stay partial and are translated again on the next run
+            if !succeeded {
+                println!("Translating {} failed, it will be translated again on the next run", file);
+                failed.insert(relative_path.clone());
+            }
+            let complete = !failed.contains(relative_path) && !docs.has_pending(relative_path);
+            manifest.record(relative_path, hash, vec![
+                ManifestOutput::File { path: format!("{file}{RECORDS_SUFFIX}") },
+                ManifestOutput::File { path: format!("{file}{TRANSLATED_SUFFIX}") },
+            ], complete);
+        }
 
-        if let Err(e) = save_progress(&progress, TRANSLATOR_PROGRESS_FILE) {
+        if let Err(e) = save_progress(&progress, &settings.progress_file) {
             println!("Failed to save progress file: {:#?}", e);
         }
 
-        if done >= to_process as u64 {
+        if incremental {
+            if let Err(e) = manifest.save(&settings.manifest_file) {
+                println!("Failed to save manifest: {:#?}", e);
+            }
+        }
+
+        // Only an explicit bound stops early, as spreadsheets yield a document per row
+        if progress.files_to_process.is_some_and(|bound| done >= bound as u64) {
             break;
         }
     }
 
     progress_bar.finish_with_message("Processing complete!");
+    docs.report().print();
+}
+
+/// Whether a document is not already in `translate.target_language`. Documents of unknown language are translated.
+pub fn needs_translation(document: &Doc) -> bool {
+    let target = normalize_language(&config().translate.target_language);
+    document.metadata.language.as_deref() != Some(target.as_str())
+}
+
+/// Prepends the document metadata as YAML front matter with `language` set to the target language,
+/// so the translation loads back tagged with the language it is written in.
+fn with_target_language(document: &Doc, content: String) -> String {
+    let metadata = DocMetadata {
+        language: Some(normalize_language(&config().translate.target_language)),
+        ..document.metadata.clone()
+    };
+    match serde_yaml::to_string(&metadata) {
+        Ok(yaml) => format!("---\n{yaml}---\n\n{content}"),
+        Err(e) => {
+            println!("Failed writing front matter for {}: {:#?}", document.file_name, e);
+            content
+        },
+    }
+}
+
+/// Whether `path` is a translator output whose source file is still next to it.
+/// Table rows are written as `{file}#{key}` outputs, so the part before a `#` is tried as well.
+pub fn is_translator_output(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    [TRANSLATED_SUFFIX, RECORDS_SUFFIX].iter()
+        .filter_map(|suffix| name.strip_suffix(suffix))
+        .any(|source| {
+            Path::new(source).is_file()
+                || source.match_indices('#').any(|(i, _)| Path::new(&source[..i]).is_file())
+        })
 }
 
+pub fn translation_prompt(passage: String) -> Prompt {
+    Prompt::One(
+        config().translate.system_msg.clone(),
+        passage
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::docs::metadata::{front_matter_metadata, split_front_matter};
+    use super::*;
+
+    #[test]
+    fn only_documents_in_other_or_unknown_languages_are_translated() {
+        let target = normalize_language(&config().translate.target_language);
+        let document = |language: Option<&str>| Doc {
+            id: "1".to_string(),
+            content: String::new(),
+            file_name: "a.md".to_string(),
+            relative_path: "a.md".to_string(),
+            pages: vec![],
+            metadata: DocMetadata { language: language.map(str::to_string), ..Default::default() },
+        };
+        assert!(!needs_translation(&document(Some(&target))));
+        assert!(needs_translation(&document(Some("xx"))));
+        assert!(needs_translation(&document(None)));
+    }
+
+    #[test]
+    fn translations_carry_the_target_language() {
+        let document = Doc {
+            id: "1".to_string(),
+            content: "Besedilo".to_string(),
+            file_name: "a.md".to_string(),
+            relative_path: "a.md".to_string(),
+            pages: vec![],
+            metadata: DocMetadata { title: Some("Naslov".to_string()), language: Some("sl".to_string()), ..Default::default() },
+        };
+        let translated = with_target_language(&document, "Text".to_string());
+
+        let (yaml, body) = split_front_matter(&translated).unwrap();
+        let metadata = front_matter_metadata(yaml).unwrap();
+        assert_eq!(body, "Text");
+        assert_eq!(metadata.title.as_deref(), Some("Naslov"));
+        assert_eq!(metadata.language, Some(normalize_language(&config().translate.target_language)));
+    }
+}
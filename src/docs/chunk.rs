@@ -0,0 +1,115 @@
+use serde::{Serialize, Deserialize};
+
+use crate::controllers::splitter::SplitStrategy;
+
+use super::doc::{chunk_id, DocMetadata};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Chunk {
+    pub doc_id: String,
+    /// Position of the chunk within its document
+    #[serde(default)]
+    pub index: usize,
+    pub file_name: String,
+    #[serde(default)]
+    pub relative_path: String,
+    pub text: String,
+    /// Markdown headings the chunk falls under (markdown splitting only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headings: Vec<String>,
+    /// Position of the chunk in the content of its document
+    #[serde(default, flatten)]
+    pub span: ChunkSpan,
+    /// Strategy of the splitter that produced the chunk
+    #[serde(default)]
+    pub strategy: SplitStrategy,
+    pub keywords: Option<String>,
+    /// Source pages the chunk was taken from (paged sources only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pages: Vec<usize>,
+    /// Metadata of the source document
+    #[serde(default)]
+    pub metadata: DocMetadata,
+    /// Relative paths of other documents containing the same passage (see the dedup stage)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternate_sources: Vec<String>,
+}
+
+impl Chunk {
+    pub fn id(&self) -> String {
+        chunk_id(&self.doc_id, self.index)
+    }
+}
+
+/// Byte and line range of a chunk in the content of its document.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct ChunkSpan {
+    pub start_byte: usize,
+    pub end_byte: usize,
+    /// First line of the chunk, counting from 1
+    pub start_line: usize,
+    /// Last line of the chunk, inclusive
+    pub end_line: usize,
+}
+
+impl ChunkSpan {
+    pub fn new(content: &str, start: usize, end: usize) -> Self {
+        let start_line = content[..start].matches('\n').count() + 1;
+        Self {
+            start_byte: start,
+            end_byte: end,
+            start_line,
+            end_line: start_line + content[start..end].trim_end_matches('\n').matches('\n').count(),
+        }
+    }
+}
+
+/// Larger passage around an embedded chunk, for the answering model (see `decorate.parents`).
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ParentChunk {
+    /// Position of the parent within its document. In `window` mode the index of the chunk
+    /// the window is centered on, so neighbouring chunks have different parents.
+    pub index: usize,
+    pub text: String,
+    #[serde(flatten)]
+    pub span: ChunkSpan,
+}
+
+/// Where a chunk sent to a model came from, saved with the model's response.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Provenance {
+    /// Relative path of the source document
+    pub source: String,
+    /// Position of the chunk within its document
+    pub index: usize,
+    #[serde(flatten)]
+    pub span: ChunkSpan,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headings: Vec<String>,
+    pub strategy: SplitStrategy,
+}
+
+/// A prompt made from a chunk and the model's response (or error, if `success` is false).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProcessedDocumentChunk {
+    pub input: String,
+    pub output: String,
+    pub success: bool,
+    #[serde(flatten)]
+    pub provenance: Provenance,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spans_count_lines_from_one() {
+        let content = "Prvi.\n\nDrugi\nodstavek.\n\nTretji.";
+        let start = content.find("Drugi").unwrap();
+        let span = ChunkSpan::new(content, start, start + "Drugi\nodstavek.\n".len());
+        assert_eq!((span.start_line, span.end_line), (3, 4));
+        assert_eq!(ChunkSpan::new(content, 0, 5).end_line, 1);
+    }
+}
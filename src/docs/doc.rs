@@ -1,8 +1,79 @@
 use serde::{Serialize, Deserialize};
+use uuid::Uuid;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Doc {
     pub id: String,
     pub content: String,
     pub file_name: String,
-}
\ No newline at end of file
+    /// Path relative to the folder the document was loaded from
+    #[serde(default)]
+    pub relative_path: String,
+    /// Byte ranges of `content` that came from each page of a paged source (PDF)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pages: Vec<PageSpan>,
+    #[serde(default)]
+    pub metadata: DocMetadata,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct DocMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub faculty: Option<String>,
+    /// Source format (`pdf`, `html`, `markdown`,...) unless set in the front matter
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub document_type: Option<String>,
+    /// RFC 3339 timestamp
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PageSpan {
+    pub page: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Doc {
+    /// Returns the page numbers that overlap the `start..end` byte range of the content.
+    pub fn pages_in(&self, start: usize, end: usize) -> Vec<usize> {
+        let mut pages: Vec<usize> = self.pages
+            .iter()
+            .filter(|span| span.start < end && start < span.end)
+            .map(|span| span.page)
+            .collect();
+        pages.dedup();
+        pages
+    }
+}
+
+/// Derives a document ID from its relative path and content, so loading the
+/// same file twice yields the same ID.
+pub fn document_id(relative_path: &str, content: &str) -> String {
+    let name = format!("{}\0{}", relative_path, content);
+    Uuid::new_v5(&Uuid::NAMESPACE_OID, name.as_bytes()).to_string()
+}
+
+/// Derives the ID of a document made from a table row. It only depends on the
+/// row key, so edits to the row overwrite the same Qdrant points.
+pub fn row_id(relative_path: &str, key: &str) -> String {
+    let name = format!("{}#{}", relative_path, key);
+    Uuid::new_v5(&Uuid::NAMESPACE_OID, name.as_bytes()).to_string()
+}
+
+/// Derives the ID of the `index`-th chunk of a document. Used as the Qdrant
+/// point ID, so re-running the pipeline upserts instead of duplicating points.
+pub fn chunk_id(doc_id: &str, index: usize) -> String {
+    let namespace = Uuid::parse_str(doc_id).unwrap_or(Uuid::NAMESPACE_OID);
+    Uuid::new_v5(&namespace, index.to_string().as_bytes()).to_string()
+}
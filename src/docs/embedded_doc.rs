@@ -1,13 +1,50 @@
 use serde::{Serialize, Deserialize};
 
+use crate::controllers::splitter::SplitStrategy;
+
+use super::{chunk::{ChunkSpan, ParentChunk}, doc::DocMetadata};
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct EmbeddedDoc {
+    /// Qdrant point ID, derived from the source document and chunk index
+    #[serde(default)]
+    pub id: String,
     pub vector: Vec<f32>,
     pub content: Passage,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Passage {
     pub usage: u32,
     pub text: String,
+    /// ID of the source document
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub doc_id: String,
+    /// Relative path of the source document
+    #[serde(default)]
+    pub source: String,
+    /// Position of the passage within its document
+    #[serde(default)]
+    pub index: usize,
+    /// Markdown headings the passage falls under
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headings: Vec<String>,
+    /// Byte and line range of the passage in the source document
+    #[serde(default, flatten)]
+    pub span: ChunkSpan,
+    /// Strategy of the splitter that produced the passage
+    #[serde(default)]
+    pub strategy: SplitStrategy,
+    /// Larger passage around this one, if `decorate.parents` is enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<ParentChunk>,
+    /// Source pages of the passage (paged sources only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pages: Vec<usize>,
+    /// Other documents containing the same passage, found by the `dedup` stage of a pipeline only
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternate_sources: Vec<String>,
+    /// Source document metadata, stored as top level payload fields so Qdrant can filter on them
+    #[serde(default, flatten)]
+    pub metadata: DocMetadata,
 }
\ No newline at end of file
@@ -0,0 +1,95 @@
+use std::{fs, path::Path};
+
+use anyhow::Result;
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8};
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+use crate::config::config;
+
+pub struct Decoded {
+    pub text: String,
+    /// Name of the encoding the bytes were decoded from
+    pub encoding: &'static str,
+    /// Whether some byte sequences were invalid and replaced with U+FFFD
+    pub malformed: bool,
+}
+
+impl Decoded {
+    pub fn transcoded(&self) -> bool {
+        self.encoding != UTF_8.name()
+    }
+}
+
+/// Reads a text file in any encoding as NFC normalized UTF-8.
+pub fn decode_file(path: &Path) -> Result<Decoded> {
+    Ok(decode_bytes(&fs::read(path)?))
+}
+
+/// Decodes bytes using their BOM if present, as UTF-8 if they are valid UTF-8 and
+/// otherwise in the encoding guessed by `chardetng` (Windows-1250, ISO-8859-2,...).
+pub fn decode_bytes(bytes: &[u8]) -> Decoded {
+    let encoding = match Encoding::for_bom(bytes) {
+        Some((encoding, _)) => encoding,
+        None if std::str::from_utf8(bytes).is_ok() => UTF_8,
+        None => {
+            let mut detector = EncodingDetector::new();
+            detector.feed(bytes, true);
+            let tld = config().loader.encoding_tld.as_deref().map(str::as_bytes);
+            detector.guess(tld, true)
+        },
+    };
+
+    // `decode` strips the BOM
+    let (text, used, malformed) = encoding.decode(bytes);
+    Decoded {
+        text: normalize(&text),
+        encoding: used.name(),
+        malformed,
+    }
+}
+
+/// Composes characters to NFC, so `č` written as `c` + caron tokenizes like the precomposed `č`.
+pub fn normalize(text: &str) -> String {
+    if is_nfc(text) {
+        text.to_string()
+    } else {
+        text.nfc().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::{UTF_16LE, WINDOWS_1250};
+
+    use super::*;
+
+    const TEXT: &str = "Študent lahko opravlja izpit največ šestkrat. Če študent ne pristopi k izpitu, se šteje, da je izpit opravljal. Pritožbe rešuje komisija za študijske zadeve.";
+
+    #[test]
+    fn byte_order_marks_select_the_encoding() {
+        let decoded = decode_bytes(&[b"\xef\xbb\xbf".as_slice(), "Čas".as_bytes()].concat());
+        assert_eq!((decoded.text.as_str(), decoded.encoding, decoded.transcoded()), ("Čas", "UTF-8", false));
+
+        let utf16: Vec<u8> = [0xff, 0xfe].into_iter().chain("Čas".encode_utf16().flat_map(u16::to_le_bytes)).collect();
+        let decoded = decode_bytes(&utf16);
+        assert_eq!((decoded.text.as_str(), decoded.encoding, decoded.transcoded()), ("Čas", UTF_16LE.name(), true));
+    }
+
+    #[test]
+    fn legacy_encodings_are_detected() {
+        let (bytes, _, _) = WINDOWS_1250.encode(TEXT);
+        let decoded = decode_bytes(&bytes);
+        assert_eq!(decoded.text, TEXT);
+        assert!(decoded.transcoded());
+        assert!(!decoded.malformed);
+    }
+
+    #[test]
+    fn decomposed_characters_are_composed() {
+        let decomposed = "c\u{30c}rka s\u{30c}";
+        assert_eq!(normalize(decomposed), "črka š");
+        assert_eq!(decode_bytes(decomposed.as_bytes()).text, "črka š");
+        assert_eq!(normalize("že NFC"), "že NFC");
+    }
+}
@@ -0,0 +1,243 @@
+use std::path::Path;
+
+use scraper::{ElementRef, Html, Node, Selector};
+
+use crate::docs::{doc::{document_id, Doc, DocMetadata}, markdown::{collapse_whitespace, render_heading, render_table}};
+
+const MAIN_CONTENT_SELECTORS: [&str; 6] = ["main", "[role=main]", "article", "#content", ".content", "body"];
+const BOILERPLATE_TAGS: [&str; 14] = [
+    "nav", "header", "footer", "aside", "script", "style", "noscript",
+    "form", "iframe", "svg", "button", "template", "select", "head",
+];
+const BOILERPLATE_ROLES: [&str; 5] = ["navigation", "banner", "contentinfo", "complementary", "search"];
+// Matched against lowercase `id` and `class` attributes
+const BOILERPLATE_MARKERS: [&str; 14] = [
+    "cookie", "consent", "gdpr", "navbar", "menu", "breadcrumb", "footer",
+    "sidebar", "share", "social", "popup", "modal", "newsletter", "skip-link",
+];
+const INLINE_TAGS: [&str; 17] = [
+    "a", "span", "strong", "b", "em", "i", "u", "small", "sup", "sub",
+    "code", "abbr", "label", "time", "mark", "q", "cite",
+];
+
+pub fn load_html(path: &Path, relative_path: String, raw: &str) -> Doc {
+    let (content, metadata) = html_to_markdown(raw);
+    Doc {
+        id: document_id(&relative_path, &content),
+        content,
+        file_name: path.to_string_lossy().to_string(),
+        relative_path,
+        pages: vec![],
+        metadata,
+    }
+}
+
+/// Extracts the main content of an HTML page as markdown, along with the
+/// page `<title>`, canonical URL and `lang` attribute.
+pub fn html_to_markdown(raw: &str) -> (String, DocMetadata) {
+    let html = Html::parse_document(raw);
+
+    let metadata = DocMetadata {
+        title: select_first(&html, "title")
+            .map(|t| collapse_whitespace(&t.text().collect::<String>()))
+            .filter(|t| !t.is_empty()),
+        source_url: select_first(&html, "link[rel=canonical]")
+            .and_then(|l| l.attr("href"))
+            .or_else(|| select_first(&html, "meta[property='og:url']").and_then(|m| m.attr("content")))
+            .map(|u| u.trim().to_string()),
+        language: select_first(&html, "html")
+            .and_then(|h| h.attr("lang"))
+            .map(|l| l.trim().to_string())
+            .filter(|l| !l.is_empty()),
+        ..Default::default()
+    };
+
+    let root = MAIN_CONTENT_SELECTORS
+        .iter()
+        .find_map(|s| select_first(&html, s))
+        .unwrap_or_else(|| html.root_element());
+
+    // The root itself is never treated as boilerplate, e.g. `<div id="content" class="with-sidebar">`
+    let mut blocks = vec![];
+    render_element(root, &mut blocks);
+    (blocks.join("\n\n"), metadata)
+}
+
+fn select_first<'a>(html: &'a Html, selector: &str) -> Option<ElementRef<'a>> {
+    let selector = Selector::parse(selector).ok()?;
+    html.select(&selector).next()
+}
+
+fn is_boilerplate(element: ElementRef) -> bool {
+    let value = element.value();
+    if BOILERPLATE_TAGS.contains(&value.name()) {
+        return true;
+    }
+    if value.attr("aria-hidden") == Some("true") {
+        return true;
+    }
+    if value.attr("role").is_some_and(|r| BOILERPLATE_ROLES.contains(&r)) {
+        return true;
+    }
+    let markers = format!("{} {}", value.id().unwrap_or(""), value.attr("class").unwrap_or("")).to_lowercase();
+    BOILERPLATE_MARKERS.iter().any(|m| markers.contains(m))
+}
+
+fn render_block(element: ElementRef, blocks: &mut Vec<String>) {
+    if !is_boilerplate(element) {
+        render_element(element, blocks);
+    }
+}
+
+fn render_element(element: ElementRef, blocks: &mut Vec<String>) {
+    match element.value().name() {
+        name @ ("h1" | "h2" | "h3" | "h4" | "h5" | "h6") => {
+            let level = name[1..].parse::<usize>().unwrap_or(1);
+            push_block(blocks, render_heading(level, &inline_text(element)));
+        },
+        "p" => push_block(blocks, inline_text(element)),
+        "ul" | "ol" => {
+            let mut lines = vec![];
+            render_list(element, 0, &mut lines);
+            push_block(blocks, lines.join("\n"));
+        },
+        "table" => push_block(blocks, render_table(&table_rows(element))),
+        "pre" => push_block(blocks, format!("```\n{}\n```", element.text().collect::<String>().trim_end())),
+        "br" | "hr" | "img" => (),
+        _ => {
+            // Container element: loose inline content becomes a paragraph
+            let mut inline = String::new();
+            for child in element.children() {
+                match child.value() {
+                    Node::Text(text) => inline.push_str(text),
+                    Node::Element(child_element) => {
+                        let child = match ElementRef::wrap(child) {
+                            Some(c) => c,
+                            None => continue,
+                        };
+                        if INLINE_TAGS.contains(&child_element.name()) {
+                            inline.push_str(&child.text().collect::<String>());
+                        } else {
+                            push_block(blocks, collapse_whitespace(&inline));
+                            inline.clear();
+                            render_block(child, blocks);
+                        }
+                    },
+                    _ => (),
+                }
+            }
+            push_block(blocks, collapse_whitespace(&inline));
+        },
+    }
+}
+
+fn render_list(list: ElementRef, depth: usize, lines: &mut Vec<String>) {
+    let ordered = list.value().name() == "ol";
+    let indent = "  ".repeat(depth);
+
+    for (index, item) in list.child_elements().filter(|e| e.value().name() == "li").enumerate() {
+        let mut text = String::new();
+        let mut nested = vec![];
+        for child in item.children() {
+            match child.value() {
+                Node::Text(t) => text.push_str(t),
+                Node::Element(e) if e.name() == "ul" || e.name() == "ol" => {
+                    if let Some(sublist) = ElementRef::wrap(child) {
+                        nested.push(sublist);
+                    }
+                },
+                Node::Element(_) => {
+                    if let Some(c) = ElementRef::wrap(child) {
+                        text.push(' ');
+                        text.push_str(&c.text().collect::<String>());
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        let marker = if ordered { format!("{}.", index + 1) } else { "-".to_string() };
+        lines.push(format!("{}{} {}", indent, marker, collapse_whitespace(&text)));
+
+        for sublist in nested {
+            render_list(sublist, depth + 1, lines);
+        }
+    }
+}
+
+fn table_rows(table: ElementRef) -> Vec<Vec<String>> {
+    let row_selector = Selector::parse("tr").unwrap();
+    table
+        .select(&row_selector)
+        .map(|row| {
+            row.child_elements()
+                .filter(|c| c.value().name() == "td" || c.value().name() == "th")
+                .map(inline_text)
+                .collect()
+        })
+        .collect()
+}
+
+fn inline_text(element: ElementRef) -> String {
+    collapse_whitespace(&element.text().collect::<String>())
+}
+
+fn push_block(blocks: &mut Vec<String>, block: String) {
+    if !block.trim().is_empty() {
+        blocks.push(block);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headings_paragraphs_and_lists_become_markdown() {
+        let (content, _) = html_to_markdown(r#"<body>
+            <h1>Študijski   red</h1>
+            <p>Velja za <strong>vse</strong> študente.</p>
+            <h3>Roki</h3>
+            <ol><li>Prijava<ul><li>do 1. 9.</li></ul></li><li>Vpis</li></ol>
+        </body>"#);
+
+        assert_eq!(content, "# Študijski red\n\nVelja za vse študente.\n\n### Roki\n\n1. Prijava\n  - do 1. 9.\n2. Vpis");
+    }
+
+    #[test]
+    fn tables_become_markdown_tables() {
+        let (content, _) = html_to_markdown(r#"<main><table>
+            <tr><th>Predmet</th><th>ECTS</th></tr>
+            <tr><td>Analiza | I</td><td>6</td></tr>
+            <tr><td>Seminar</td></tr>
+        </table></main>"#);
+
+        assert_eq!(content, "| Predmet | ECTS |\n| --- | --- |\n| Analiza \\| I | 6 |\n| Seminar |  |");
+    }
+
+    #[test]
+    fn scripts_navigation_and_cookie_banners_are_dropped() {
+        let (content, _) = html_to_markdown(r#"<body>
+            <nav><a href="/">Domov</a></nav>
+            <div role="navigation">Meni</div>
+            <script>track();</script>
+            <div class="cookie-notice">Piškotki</div>
+            <p>Vsebina</p>
+            <footer>© UP</footer>
+        </body>"#);
+
+        assert_eq!(content, "Vsebina");
+    }
+
+    #[test]
+    fn main_content_and_metadata_are_extracted() {
+        let (content, metadata) = html_to_markdown(r#"<html lang="sl"><head>
+            <title> Pravilnik </title><link rel="canonical" href="https://www.upr.si/pravilnik">
+        </head><body><header>Glava</header><article><p>Besedilo</p></article><p>Izven</p></body></html>"#);
+
+        assert_eq!(content, "Besedilo");
+        assert_eq!(metadata.title.as_deref(), Some("Pravilnik"));
+        assert_eq!(metadata.source_url.as_deref(), Some("https://www.upr.si/pravilnik"));
+        assert_eq!(metadata.language.as_deref(), Some("sl"));
+    }
+}
@@ -0,0 +1,90 @@
+use crate::config::config;
+
+// Detection is linear in the text length and the start of a document is representative enough
+const SAMPLE_BYTES: usize = 20_000;
+
+// ISO 639-3 codes returned by `whatlang` mapped to the ISO 639-1 codes used in HTML and front matter
+const ISO_639_1: [(&str, &str); 24] = [
+    ("slv", "sl"), ("eng", "en"), ("hrv", "hr"), ("srp", "sr"), ("deu", "de"), ("ita", "it"),
+    ("hun", "hu"), ("fra", "fr"), ("spa", "es"), ("por", "pt"), ("nld", "nl"), ("pol", "pl"),
+    ("ces", "cs"), ("slk", "sk"), ("rus", "ru"), ("ukr", "uk"), ("bul", "bg"), ("mkd", "mk"),
+    ("ron", "ro"), ("dan", "da"), ("swe", "sv"), ("nob", "nb"), ("fin", "fi"), ("tur", "tr"),
+];
+
+/// Detects the language of a text offline with `whatlang`.
+///
+/// Returns the ISO 639-1 code where one exists (ISO 639-3 otherwise), or `None`
+/// if the detection confidence is below `loader.language_min_confidence`.
+pub fn detect_language(text: &str) -> Option<String> {
+    detect_with_confidence(text, config().loader.language_min_confidence)
+}
+
+fn detect_with_confidence(text: &str, min_confidence: f64) -> Option<String> {
+    let mut end = text.len().min(SAMPLE_BYTES);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    let info = whatlang::detect(&text[..end])?;
+    if info.confidence() < min_confidence {
+        return None;
+    }
+    Some(normalize_language(info.lang().code()))
+}
+
+/// Brings language tags like `en-US`, `SL` or `slv` to the form `detect_language` returns.
+pub fn normalize_language(tag: &str) -> String {
+    let primary = tag
+        .trim()
+        .split(['-', '_'])
+        .next()
+        .unwrap_or("")
+        .to_lowercase();
+    ISO_639_1
+        .iter()
+        .find(|(iso3, _)| *iso3 == primary)
+        .map(|(_, iso1)| iso1.to_string())
+        .unwrap_or(primary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn languages_are_detected_as_iso_639_1() {
+        let slovene = "Študent lahko opravlja izpit iz istega predmeta največ šestkrat. Po tretjem neuspešnem opravljanju izpita lahko zahteva komisijski izpit.";
+        let english = "A student may take the exam in the same course at most six times. After the third failed attempt the student may request an examination before a committee.";
+        assert_eq!(detect_language(slovene).as_deref(), Some("sl"));
+        assert_eq!(detect_language(english).as_deref(), Some("en"));
+    }
+
+    #[test]
+    fn detection_below_the_configured_confidence_is_unknown() {
+        let text = "Študent lahko opravlja izpit iz istega predmeta največ šestkrat.";
+        assert_eq!(detect_with_confidence(text, 0.0).as_deref(), Some("sl"));
+        assert_eq!(detect_with_confidence(text, 1.01), None);
+    }
+
+    #[test]
+    fn undetectable_texts_are_unknown() {
+        assert_eq!(detect_language(""), None);
+        assert_eq!(detect_language("2024/25 | 6 | 180"), None);
+        assert_eq!(detect_language("ok"), None);
+    }
+
+    #[test]
+    fn long_texts_are_sampled_on_a_char_boundary() {
+        let text = "č".repeat(SAMPLE_BYTES) + " Študent lahko opravlja izpit.";
+        detect_language(&text);
+    }
+
+    #[test]
+    fn language_tags_are_normalized() {
+        assert_eq!(normalize_language(" en-US"), "en");
+        assert_eq!(normalize_language("SL"), "sl");
+        assert_eq!(normalize_language("slv"), "sl");
+        assert_eq!(normalize_language("pt_BR"), "pt");
+        assert_eq!(normalize_language("epo"), "epo");
+    }
+}
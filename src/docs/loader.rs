@@ -1,40 +1,376 @@
-use std::{fs::{self, File}, io::{BufRead, BufReader, Read}, path::Path};
+use std::{collections::VecDeque, fs::File, io::{BufRead, BufReader}, path::{Path, PathBuf}};
 
-use anyhow::Result;
+use anyhow::{Error, Result};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::de::DeserializeOwned;
+use walkdir::WalkDir;
 
-use crate::docs::doc::Doc;
+use crate::{config::config, controllers::translator::is_translator_output, docs::{doc::{document_id, Doc, DocMetadata}, encoding::decode_file, html_loader::load_html, language::{detect_language, normalize_language}, metadata::{add_file_metadata, front_matter_metadata, split_front_matter}, office_loader::{load_docx, load_odt}, pdf_loader::load_pdf, table_loader::{load_csv, load_xlsx}}};
 
 pub fn load_jsonl_data(file_name: &str) -> Result<Vec<Doc>> {
+    load_jsonl_records(file_name)
+}
+
+pub fn load_jsonl_records<T: DeserializeOwned>(file_name: &str) -> Result<Vec<T>> {
     let file = File::open(file_name)?;
     let reader = BufReader::new(file);
-    let mut docs = vec![];
+    let mut records = vec![];
     for line in reader.lines() {
         let line = line?;
-        let doc: Doc = serde_json::from_str(&line)?;
-        docs.push(doc)
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: T = serde_json::from_str(&line)?;
+        records.push(record)
     }
-    Ok(docs)
+    Ok(records)
 }
 
 
 pub fn load_data(folder_name: &str) -> Result<Vec<Doc>> {
-    let mut data = Vec::new();
-    let path = Path::new(folder_name);
-
-    for entry in fs::read_dir(path)? {
-        let entry = entry?;
-        let path = entry.path();
-
-        if path.is_file() && (path.extension().map_or(false, |ext| ext == "txt" || ext == "md")) {
-            let mut file = fs::File::open(path.clone())?;
-            let mut contents = String::new();
-            file.read_to_string(&mut contents)?;
-            data.push(Doc {
-                id: uuid::Uuid::new_v4().to_string(),
-                content: contents,
-                file_name: path.to_str().unwrap().to_string(),
-            });
-        }
-    }
-    Ok(data)
-}
\ No newline at end of file
+    let mut stream = stream_data(folder_name)?;
+    let docs = stream.by_ref().collect();
+    stream.report().print();
+    Ok(docs)
+}
+
+/// Lists the documents in `folder_name` without reading them. Each file is
+/// loaded only when the returned stream reaches it.
+pub fn stream_data(folder_name: &str) -> Result<DocStream> {
+    Ok(DocStream::from_files(list_files(folder_name)?))
+}
+
+/// Lazily loading iterator over the documents of a folder. Files that fail to
+/// load are skipped and collected in the `report`, along with files that didn't
+/// decode cleanly. Tables yield a document per row.
+pub struct DocStream {
+    files: std::vec::IntoIter<(PathBuf, String)>,
+    // Remaining rows of the last loaded table
+    buffered: VecDeque<Doc>,
+    report: LoadReport,
+}
+
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    pub loaded: usize,
+    pub transcoded: usize,
+    /// Relative path and description of every file that failed to load or decode cleanly
+    pub problems: Vec<(String, String)>,
+}
+
+impl LoadReport {
+    pub fn print(&self) {
+        println!(
+            "Loaded {} documents, {} of them transcoded to UTF-8. {} files with problems.",
+            self.loaded,
+            self.transcoded,
+            self.problems.len()
+        );
+        for (file, problem) in &self.problems {
+            println!("  {}: {}", file, problem);
+        }
+    }
+}
+
+impl DocStream {
+    pub fn from_files(files: Vec<(PathBuf, String)>) -> Self {
+        Self {
+            files: files.into_iter(),
+            buffered: VecDeque::new(),
+            report: LoadReport::default(),
+        }
+    }
+
+    /// Returns the files not yet loaded, with their relative paths.
+    pub fn into_files(self) -> Vec<(PathBuf, String)> {
+        self.files.collect()
+    }
+
+    /// Number of files not yet loaded.
+    pub fn remaining_files(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Skips the next `count` documents, e.g. when resuming from a progress file.
+    /// Only tables are read, to count their rows, other files are skipped unread.
+    pub fn skip_docs(mut self, count: usize) -> Self {
+        let mut skipped = self.buffered.len().min(count);
+        self.buffered.drain(..skipped);
+        while skipped < count {
+            let (path, relative_path) = match self.files.next() {
+                Some(f) => f,
+                None => break,
+            };
+            if !is_table(&path) {
+                skipped += 1;
+                continue;
+            }
+            match load_file(&path, relative_path.clone(), &mut self.report) {
+                Ok(docs) => {
+                    let rows = docs.len().min(count - skipped);
+                    skipped += rows;
+                    self.buffered.extend(docs.into_iter().skip(rows));
+                },
+                Err(e) => {
+                    println!("Failed loading {}: {}", path.display(), e);
+                    self.report.problems.push((relative_path, format!("failed to load: {}", e)));
+                },
+            }
+        }
+        self
+    }
+
+    /// What was loaded so far, to print at the end of a run.
+    pub fn report(&self) -> &LoadReport {
+        &self.report
+    }
+
+    /// Whether documents of the file are still waiting in the stream, e.g. further rows of a table.
+    pub fn has_pending(&self, relative_path: &str) -> bool {
+        self.buffered.iter().any(|d| d.relative_path == relative_path)
+    }
+
+    /// Loads up to `size` documents. An empty batch means the stream is exhausted.
+    pub fn next_batch(&mut self, size: usize) -> Vec<Doc> {
+        self.by_ref().take(size.max(1)).collect()
+    }
+}
+
+impl Iterator for DocStream {
+    type Item = Doc;
+
+    fn next(&mut self) -> Option<Doc> {
+        if let Some(doc) = self.buffered.pop_front() {
+            self.report.loaded += 1;
+            return Some(doc);
+        }
+        for (path, relative_path) in self.files.by_ref() {
+            match load_file(&path, relative_path.clone(), &mut self.report) {
+                Ok(docs) => {
+                    self.buffered.extend(docs);
+                    if let Some(doc) = self.buffered.pop_front() {
+                        self.report.loaded += 1;
+                        return Some(doc);
+                    }
+                },
+                Err(e) => {
+                    println!("Failed loading {}: {}", path.display(), e);
+                    self.report.problems.push((relative_path, format!("failed to load: {}", e)));
+                },
+            }
+        }
+        None
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+fn is_table(path: &Path) -> bool {
+    matches!(extension(path).as_str(), "csv" | "xlsx")
+}
+
+fn load_file(path: &Path, relative_path: String, report: &mut LoadReport) -> Result<Vec<Doc>> {
+    let extension = extension(path);
+    let mut docs = match extension.as_str() {
+        "pdf" => vec![load_pdf(path, relative_path)?],
+        "docx" => vec![load_docx(path, relative_path)?],
+        "odt" => vec![load_odt(path, relative_path)?],
+        "xlsx" => load_xlsx(path, &relative_path)?,
+        _ => {
+            let decoded = decode_file(path)?;
+            if decoded.transcoded() {
+                report.transcoded += 1;
+            }
+            if decoded.malformed {
+                report.problems.push((
+                    relative_path.clone(),
+                    format!("invalid {} byte sequences replaced with U+FFFD", decoded.encoding),
+                ));
+            }
+            match extension.as_str() {
+                "csv" => load_csv(path, &relative_path, &decoded.text)?,
+                "html" | "htm" => vec![load_html(path, relative_path, &decoded.text)],
+                "md" | "markdown" => vec![load_markdown(path, relative_path, decoded.text)],
+                _ => vec![load_text(path, relative_path, decoded.text)],
+            }
+        },
+    };
+    for doc in docs.iter_mut() {
+        add_file_metadata(&mut doc.metadata, path);
+        doc.metadata.language = match &doc.metadata.language {
+            Some(tag) => Some(normalize_language(tag)),
+            None if config().loader.detect_language => detect_language(&doc.content),
+            None => None,
+        };
+    }
+    Ok(docs)
+}
+
+fn load_text(path: &Path, relative_path: String, content: String) -> Doc {
+    Doc {
+        id: document_id(&relative_path, &content),
+        content,
+        file_name: path.to_string_lossy().to_string(),
+        relative_path,
+        pages: vec![],
+        metadata: DocMetadata::default(),
+    }
+}
+
+/// Builds a markdown document, moving its YAML front matter into the document metadata.
+fn load_markdown(path: &Path, relative_path: String, content: String) -> Doc {
+    let doc = load_text(path, relative_path, content);
+    let (metadata, content) = match split_front_matter(&doc.content) {
+        Some((yaml, body)) => match front_matter_metadata(yaml) {
+            Ok(metadata) => (metadata, body.to_string()),
+            Err(e) => {
+                println!("Ignoring invalid front matter in {}: {}", path.display(), e);
+                return doc;
+            },
+        },
+        None => return doc,
+    };
+    Doc {
+        content,
+        metadata,
+        ..doc
+    }
+}
+
+/// Lists files under `folder_name` that match the configured include globs and
+/// none of the exclude globs, along with their `/` separated relative paths.
+pub fn list_files(folder_name: &str) -> Result<Vec<(PathBuf, String)>> {
+    let settings = &config().loader;
+    let include = build_globset(&settings.include)?;
+    let exclude = build_globset(&settings.exclude)?;
+    let root = Path::new(folder_name);
+    let max_depth = if settings.recursive { usize::MAX } else { 1 };
+
+    let walker = WalkDir::new(root)
+        .follow_links(settings.follow_symlinks)
+        .max_depth(max_depth)
+        .sort_by_file_name();
+
+    let mut files = vec![];
+    for entry in walker {
+        let entry = match entry {
+            Ok(e) => e,
+            Err(e) => {
+                // Broken links and symlink loops end up here
+                println!("Skipping entry while walking {}: {}", folder_name, e);
+                continue;
+            },
+        };
+        // Without `follow_symlinks` links are reported as symlinks and skipped
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        // Translations are written next to their sources and would otherwise be loaded again
+        if is_translator_output(entry.path()) {
+            continue;
+        }
+        if include.is_match(relative) && !exclude.is_match(relative) {
+            let relative_path = relative.to_string_lossy().replace('\\', "/");
+            files.push((entry.path().to_path_buf(), relative_path));
+        }
+    }
+    Ok(files)
+}
+
+pub fn build_globset(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(|e| Error::msg(format!("Invalid glob pattern '{}': {}", pattern, e)))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A text file between two tables with three and two rows
+    fn write_folder(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.csv"), "ime;ects\nMatematika;6\nFizika;5\nKemija;4\n").unwrap();
+        std::fs::write(root.join("b.txt"), "Besedilo").unwrap();
+        std::fs::write(root.join("c.csv"), "ime\nBiologija\nGeografija\n").unwrap();
+        root
+    }
+
+    fn contents(stream: DocStream) -> Vec<String> {
+        stream.map(|d| d.content).collect()
+    }
+
+    #[test]
+    fn tables_stream_a_document_per_row() {
+        let root = write_folder("stream_test");
+        let stream = stream_data(&root.to_string_lossy()).unwrap();
+        assert_eq!(stream.remaining_files(), 3);
+        assert_eq!(contents(stream), [
+            "ime: Matematika\nects: 6", "ime: Fizika\nects: 5", "ime: Kemija\nects: 4",
+            "Besedilo", "ime: Biologija", "ime: Geografija",
+        ]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn skipping_counts_table_rows() {
+        let root = write_folder("skip_test");
+        let stream = || stream_data(&root.to_string_lossy()).unwrap();
+
+        assert_eq!(contents(stream().skip_docs(2)), ["ime: Kemija\nects: 4", "Besedilo", "ime: Biologija", "ime: Geografija"]);
+        assert_eq!(contents(stream().skip_docs(4)), ["ime: Biologija", "ime: Geografija"]);
+        assert_eq!(contents(stream().skip_docs(5)), ["ime: Geografija"]);
+        assert!(contents(stream().skip_docs(10)).is_empty());
+
+        // Skipping again drains the buffered rows first
+        let mut resumed = stream().skip_docs(1);
+        assert_eq!(resumed.next().unwrap().content, "ime: Fizika\nects: 5");
+        assert_eq!(contents(resumed.skip_docs(1)), ["Besedilo", "ime: Biologija", "ime: Geografija"]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn tables_are_pending_until_their_last_row() {
+        let root = write_folder("pending_test");
+        let mut stream = stream_data(&root.to_string_lossy()).unwrap();
+
+        assert_eq!(stream.next_batch(2).len(), 2);
+        assert!(stream.has_pending("a.csv"));
+        assert_eq!(stream.next_batch(2).len(), 2);
+        assert!(!stream.has_pending("a.csv"));
+        assert!(!stream.has_pending("b.txt"));
+
+        let mut stream = stream.skip_docs(1);
+        assert!(stream.has_pending("c.csv"));
+        assert_eq!(stream.next_batch(5).len(), 1);
+        assert!(!stream.has_pending("c.csv"));
+        assert!(stream.next_batch(5).is_empty());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn translations_next_to_their_sources_are_not_listed() {
+        let root = std::env::temp_dir().join(format!("list_files_test_{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        for name in ["a.md", "a.md_translated.md", "b.csv", "b.csv#3_translated.md", "orphan_translated.md"] {
+            std::fs::write(root.join(name), "text").unwrap();
+        }
+
+        let files = list_files(&root.to_string_lossy()).unwrap();
+        let listed: Vec<&str> = files.iter().map(|(_, relative)| relative.as_str()).collect();
+        assert_eq!(listed, ["a.md", "b.csv", "orphan_translated.md"]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
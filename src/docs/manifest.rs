@@ -0,0 +1,311 @@
+use std::{collections::{BTreeMap, HashMap, HashSet}, fs::{self, File}, io::{BufRead, BufReader, BufWriter, Write}, path::{Path, PathBuf}};
+
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+
+use super::loader::DocStream;
+
+/// Records which source files a command already processed, by content hash,
+/// and the outputs each of them produced.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Manifest {
+    /// Keyed by the path relative to the input folder
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ManifestEntry {
+    pub hash: String,
+    pub outputs: Vec<ManifestOutput>,
+    /// Not every document made from the file (e.g. table rows) was processed, so the
+    /// file is processed again on the next run
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub partial: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ManifestOutput {
+    /// A file written for the source
+    File { path: String },
+    /// A line appended to a shared JSONL file, identified by the hash of the line
+    Record { file: String, hash: String },
+    /// A Qdrant point
+    Point { id: String },
+}
+
+/// Result of comparing a folder listing against the manifest.
+#[derive(Debug, Default)]
+pub struct ManifestChanges {
+    /// New or changed files with their current hash
+    pub pending: Vec<(PathBuf, String, String)>,
+    /// Outputs of changed and deleted files, which have to be removed
+    pub stale_outputs: Vec<ManifestOutput>,
+    pub unchanged: usize,
+    pub deleted: usize,
+}
+
+impl Manifest {
+    pub fn load(file_name: &str) -> Manifest {
+        let contents = match fs::read_to_string(file_name) {
+            Ok(c) => c,
+            Err(e) => {
+                println!("No manifest loaded from {} ({}). Processing every file.", file_name, e);
+                return Manifest::default();
+            },
+        };
+        match serde_json::from_str(&contents) {
+            Ok(m) => m,
+            Err(e) => {
+                println!("Error parsing manifest {}. Processing every file: {}", file_name, e);
+                Manifest::default()
+            },
+        }
+    }
+
+    pub fn save(&self, file_name: &str) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(file_name)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Compares the listed source files against the manifest. Files that are
+    /// themselves outputs of an earlier run are ignored. Changed and deleted
+    /// files are dropped from the manifest right away.
+    pub fn changes(&mut self, files: Vec<(PathBuf, String)>) -> Result<ManifestChanges> {
+        let output_files: HashSet<PathBuf> = self.files
+            .values()
+            .flat_map(|e| e.outputs.iter())
+            .filter_map(|o| match o {
+                ManifestOutput::File { path } => Some(PathBuf::from(path)),
+                _ => None,
+            })
+            .collect();
+
+        let mut changes = ManifestChanges::default();
+        let mut listed = HashSet::new();
+        for (path, relative_path) in files {
+            if output_files.contains(&path) {
+                continue;
+            }
+            let hash = file_hash(&path)?;
+            listed.insert(relative_path.clone());
+            match self.files.get(&relative_path) {
+                Some(entry) if entry.hash == hash && !entry.partial => changes.unchanged += 1,
+                Some(_) => {
+                    if let Some(entry) = self.files.remove(&relative_path) {
+                        changes.stale_outputs.extend(entry.outputs);
+                    }
+                    changes.pending.push((path, relative_path, hash));
+                },
+                None => changes.pending.push((path, relative_path, hash)),
+            }
+        }
+
+        let deleted: Vec<String> = self.files.keys().filter(|k| !listed.contains(*k)).cloned().collect();
+        for relative_path in deleted {
+            if let Some(entry) = self.files.remove(&relative_path) {
+                changes.stale_outputs.extend(entry.outputs);
+                changes.deleted += 1;
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Records the outputs of a source file. Outputs of further documents from the
+    /// same unchanged file (e.g. table rows) are added to the existing entry. The file
+    /// stays `partial` until it is recorded as `complete`.
+    pub fn record(&mut self, relative_path: &str, hash: &str, outputs: Vec<ManifestOutput>, complete: bool) {
+        if let Some(entry) = self.files.get_mut(relative_path).filter(|e| e.hash == hash) {
+            entry.outputs.extend(outputs);
+            entry.partial = !complete;
+            return;
+        }
+        self.files.insert(relative_path.to_string(), ManifestEntry {
+            hash: hash.to_string(),
+            outputs,
+            partial: !complete,
+        });
+    }
+}
+
+impl ManifestChanges {
+    pub fn describe(&self) -> String {
+        format!(
+            "{} new or changed, {} unchanged, {} deleted",
+            self.pending.len(),
+            self.unchanged,
+            self.deleted
+        )
+    }
+}
+
+/// Hex encoded SHA-256 of the file contents.
+pub fn file_hash(path: &Path) -> Result<String> {
+    let bytes = fs::read(path)?;
+    Ok(hash_bytes(&bytes))
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Deletes output files and drops output records from their JSONL files.
+/// Qdrant points are returned for the caller to delete.
+pub fn remove_outputs(outputs: &[ManifestOutput]) -> Result<Vec<String>> {
+    let mut records: HashMap<&str, HashSet<&str>> = HashMap::new();
+    let mut points = vec![];
+
+    for output in outputs {
+        match output {
+            ManifestOutput::File { path } => {
+                if let Err(e) = fs::remove_file(path) {
+                    println!("Couldn't remove stale output {}: {}", path, e);
+                }
+            },
+            ManifestOutput::Record { file, hash } => {
+                records.entry(file).or_default().insert(hash);
+            },
+            ManifestOutput::Point { id } => points.push(id.clone()),
+        }
+    }
+
+    for (file, hashes) in records {
+        if !Path::new(file).exists() {
+            continue;
+        }
+        let kept: Vec<String> = BufReader::new(File::open(file)?)
+            .lines()
+            .collect::<std::io::Result<Vec<String>>>()?
+            .into_iter()
+            .filter(|line| !hashes.contains(hash_bytes(line.as_bytes()).as_str()))
+            .collect();
+        let mut writer = BufWriter::new(File::create(file)?);
+        for line in kept {
+            writeln!(writer, "{}", line)?;
+        }
+        writer.flush()?;
+    }
+
+    Ok(points)
+}
+
+/// Narrows `docs` down to new and changed files and removes the file and record
+/// outputs of changed and deleted ones.
+///
+/// Returns the narrowed stream, the content hashes of the files in it (by relative
+/// path) and the stale Qdrant point IDs, which the caller has to delete.
+pub fn prepare_incremental(docs: DocStream, manifest: &mut Manifest) -> Result<(DocStream, HashMap<String, String>, Vec<String>)> {
+    let changes = manifest.changes(docs.into_files())?;
+    println!("Manifest: {}", changes.describe());
+    let stale_points = remove_outputs(&changes.stale_outputs)?;
+
+    let mut files = vec![];
+    let mut hashes = HashMap::new();
+    for (path, relative_path, hash) in changes.pending {
+        hashes.insert(relative_path.clone(), hash);
+        files.push((path, relative_path));
+    }
+    Ok((DocStream::from_files(files), hashes, stale_points))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes the files into a fresh folder and lists them like the loader does.
+    fn folder(name: &str, files: &[(&str, &str)]) -> Vec<(PathBuf, String)> {
+        let dir = std::env::temp_dir().join(format!("manifest_test_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        files
+            .iter()
+            .map(|(file, contents)| {
+                let path = dir.join(file);
+                fs::write(&path, contents).unwrap();
+                (path, file.to_string())
+            })
+            .collect()
+    }
+
+    fn point(id: &str) -> ManifestOutput {
+        ManifestOutput::Point { id: id.to_string() }
+    }
+
+    #[test]
+    fn unchanged_files_are_skipped() {
+        let files = folder("unchanged", &[("a.md", "prvi"), ("b.md", "drugi")]);
+        let mut manifest = Manifest::default();
+        manifest.record("a.md", &hash_bytes(b"prvi"), vec![point("1")], true);
+        manifest.record("b.md", &hash_bytes(b"staro"), vec![point("2")], true);
+        manifest.record("c.md", &hash_bytes(b"izbrisan"), vec![point("3")], true);
+
+        let changes = manifest.changes(files).unwrap();
+        assert_eq!((changes.unchanged, changes.deleted), (1, 1));
+        let pending: Vec<&str> = changes.pending.iter().map(|(_, relative_path, _)| relative_path.as_str()).collect();
+        assert_eq!(pending, vec!["b.md"]);
+        assert_eq!(changes.stale_outputs, vec![point("2"), point("3")]);
+        assert_eq!(manifest.files.keys().collect::<Vec<_>>(), vec!["a.md"]);
+    }
+
+    #[test]
+    fn partial_files_are_processed_again() {
+        let files = folder("partial", &[("t.csv", "a,b\n1,2\n3,4")]);
+        let hash = hash_bytes(b"a,b\n1,2\n3,4");
+        let mut manifest = Manifest::default();
+        manifest.record("t.csv", &hash, vec![point("1")], false);
+
+        let changes = manifest.changes(files).unwrap();
+        assert_eq!(changes.pending.len(), 1);
+        assert_eq!(changes.stale_outputs, vec![point("1")]);
+    }
+
+    #[test]
+    fn later_rows_extend_the_entry() {
+        let mut manifest = Manifest::default();
+        manifest.record("t.csv", "hash", vec![point("1")], false);
+        manifest.record("t.csv", "hash", vec![point("2")], true);
+        let entry = &manifest.files["t.csv"];
+        assert_eq!(entry.outputs, vec![point("1"), point("2")]);
+        assert!(!entry.partial);
+
+        // A new hash replaces the outputs
+        manifest.record("t.csv", "new", vec![point("3")], true);
+        assert_eq!(manifest.files["t.csv"].outputs, vec![point("3")]);
+    }
+
+    #[test]
+    fn outputs_are_not_sources() {
+        let files = folder("outputs", &[("a.md", "prvi"), ("a.jsonl", "{}")]);
+        let mut manifest = Manifest::default();
+        let output = files[1].0.to_string_lossy().to_string();
+        manifest.record("a.md", &hash_bytes(b"prvi"), vec![ManifestOutput::File { path: output }], true);
+
+        let changes = manifest.changes(files).unwrap();
+        assert_eq!((changes.unchanged, changes.pending.len(), changes.deleted), (1, 0, 0));
+    }
+
+    #[test]
+    fn removing_outputs_drops_their_records() {
+        let files = folder("records", &[("out.jsonl", "{\"a\":1}\n{\"b\":2}\n")]);
+        let file = files[0].0.to_string_lossy().to_string();
+        let outputs = vec![
+            ManifestOutput::Record { file: file.clone(), hash: hash_bytes(b"{\"a\":1}") },
+            point("7"),
+        ];
+        assert_eq!(remove_outputs(&outputs).unwrap(), vec!["7".to_string()]);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "{\"b\":2}\n");
+    }
+
+    #[test]
+    fn complete_entries_serialize_without_the_flag() {
+        let mut manifest = Manifest::default();
+        manifest.record("a.md", "hash", vec![], true);
+        manifest.record("b.md", "hash", vec![], false);
+        let json = serde_json::to_value(&manifest).unwrap();
+        assert!(json["files"]["a.md"].get("partial").is_none());
+        assert_eq!(json["files"]["b.md"]["partial"], true);
+    }
+}
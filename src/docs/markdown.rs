@@ -0,0 +1,29 @@
+/// Renders rows of cells as a markdown table, using the first row as the header.
+pub fn render_table(rows: &[Vec<String>]) -> String {
+    let rows: Vec<&Vec<String>> = rows.iter().filter(|r| !r.is_empty()).collect();
+    let columns = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+    if columns == 0 {
+        return String::new();
+    }
+
+    let format_row = |cells: &Vec<String>| {
+        let mut padded: Vec<String> = cells
+            .iter()
+            .map(|c| collapse_whitespace(c).replace('|', "\\|"))
+            .collect();
+        padded.resize(columns, String::new());
+        format!("| {} |", padded.join(" | "))
+    };
+
+    let mut lines = vec![format_row(rows[0]), format!("|{}", " --- |".repeat(columns))];
+    lines.extend(rows[1..].iter().map(|r| format_row(r)));
+    lines.join("\n")
+}
+
+pub fn render_heading(level: usize, text: &str) -> String {
+    format!("{} {}", "#".repeat(level.clamp(1, 6)), collapse_whitespace(text))
+}
+
+pub fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
@@ -0,0 +1,142 @@
+use std::{fs, path::Path};
+
+use anyhow::Result;
+use serde_yaml::{Mapping, Value};
+
+use crate::docs::doc::DocMetadata;
+
+/// Splits a leading `---` delimited YAML front matter block from markdown content.
+/// Returns the YAML and the remaining content, or `None` if there is no front matter.
+pub fn split_front_matter(content: &str) -> Option<(&str, &str)> {
+    let rest = content.strip_prefix('\u{feff}').unwrap_or(content);
+    let rest = rest.strip_prefix("---\n").or_else(|| rest.strip_prefix("---\r\n"))?;
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" || line.trim_end() == "..." {
+            let body = &rest[offset + line.len()..];
+            return Some((&rest[..offset], body.trim_start_matches(['\r', '\n'])));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// Reads the known metadata keys from a YAML front matter block. Unknown keys are ignored.
+pub fn front_matter_metadata(yaml: &str) -> Result<DocMetadata> {
+    let mapping: Mapping = match serde_yaml::from_str::<Option<Mapping>>(yaml)? {
+        Some(m) => m,
+        None => return Ok(DocMetadata::default()),
+    };
+    let get = |keys: &[&str]| keys.iter().find_map(|k| mapping.get(*k).and_then(yaml_string));
+
+    Ok(DocMetadata {
+        title: get(&["title"]),
+        source_url: get(&["source_url", "url", "source"]),
+        language: get(&["language", "lang"]),
+        faculty: get(&["faculty"]),
+        document_type: get(&["document_type", "type"]),
+        last_modified: get(&["last_modified", "date"]),
+        tags: mapping.get("tags").map(yaml_list).unwrap_or_default(),
+    })
+}
+
+/// Fills the metadata the loaders didn't set from the file itself.
+pub fn add_file_metadata(metadata: &mut DocMetadata, path: &Path) {
+    if metadata.document_type.is_none() {
+        metadata.document_type = path
+            .extension()
+            .map(|e| document_type(&e.to_string_lossy().to_lowercase()).to_string());
+    }
+    if metadata.last_modified.is_none() {
+        metadata.last_modified = fs::metadata(path)
+            .and_then(|m| m.modified())
+            .ok()
+            .map(|t| humantime::format_rfc3339_seconds(t).to_string());
+    }
+}
+
+fn document_type(extension: &str) -> &str {
+    match extension {
+        "md" | "markdown" => "markdown",
+        "txt" => "text",
+        "htm" | "html" => "html",
+        other => other,
+    }
+}
+
+fn yaml_string(value: &Value) -> Option<String> {
+    let text = match value {
+        Value::String(s) => s.trim().to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        _ => return None,
+    };
+    if text.is_empty() { None } else { Some(text) }
+}
+
+/// Accepts both a YAML list and a comma separated string.
+fn yaml_list(value: &Value) -> Vec<String> {
+    match value {
+        Value::Sequence(items) => items.iter().filter_map(yaml_string).collect(),
+        Value::String(s) => s
+            .split(',')
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect(),
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_front_matter_fills_the_metadata() {
+        let content = "\u{feff}---\ntitle: Študijski red\nurl: https://www.upr.si/red\nlang: sl\nyear: 2024\ntags: [vpis, izpiti]\n---\n\n# Študijski red\n";
+        let (yaml, body) = split_front_matter(content).unwrap();
+        assert_eq!(body, "# Študijski red\n");
+
+        let metadata = front_matter_metadata(yaml).unwrap();
+        assert_eq!(metadata, DocMetadata {
+            title: Some("Študijski red".to_string()),
+            source_url: Some("https://www.upr.si/red".to_string()),
+            language: Some("sl".to_string()),
+            tags: vec!["vpis".to_string(), "izpiti".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(front_matter_metadata("tags: vpis, izpiti\ntitle: ''").unwrap().tags, ["vpis", "izpiti"]);
+    }
+
+    #[test]
+    fn malformed_front_matter_is_an_error() {
+        let (yaml, _) = split_front_matter("---\ntitle: [unclosed\n---\nBody").unwrap();
+        assert!(front_matter_metadata(yaml).is_err());
+        assert!(front_matter_metadata("- just\n- a list").is_err());
+    }
+
+    #[test]
+    fn content_without_front_matter_is_left_alone() {
+        assert_eq!(split_front_matter("# Naslov\n---\n"), None);
+        assert_eq!(split_front_matter("---\ntitle: never closed\n"), None);
+        assert_eq!(front_matter_metadata("").unwrap(), DocMetadata::default());
+    }
+
+    #[test]
+    fn file_metadata_does_not_override_the_front_matter() {
+        let path = std::env::temp_dir().join(format!("metadata_test_{}.md", std::process::id()));
+        fs::write(&path, "text").unwrap();
+
+        let mut metadata = DocMetadata::default();
+        add_file_metadata(&mut metadata, &path);
+        assert_eq!(metadata.document_type.as_deref(), Some("markdown"));
+        assert!(metadata.last_modified.is_some());
+
+        let mut metadata = DocMetadata { document_type: Some("pravilnik".to_string()), last_modified: Some("2024-01-01".to_string()), ..Default::default() };
+        add_file_metadata(&mut metadata, &path);
+        assert_eq!(metadata.document_type.as_deref(), Some("pravilnik"));
+        assert_eq!(metadata.last_modified.as_deref(), Some("2024-01-01"));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,5 +1,15 @@
 pub mod doc;
+pub mod chunk;
 pub mod loader;
+pub mod encoding;
+pub mod language;
+pub mod pdf_loader;
+pub mod html_loader;
+pub mod office_loader;
+pub mod table_loader;
+pub mod markdown;
+pub mod metadata;
+pub mod manifest;
 pub mod saver;
 pub mod embedded_doc;
 pub mod qdant;
\ No newline at end of file
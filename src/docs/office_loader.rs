@@ -0,0 +1,400 @@
+use std::{collections::HashMap, fs::File, io::Read, path::Path};
+
+use anyhow::{Error, Result};
+use quick_xml::{escape::unescape, events::{BytesStart, Event}, Reader};
+use zip::ZipArchive;
+
+use crate::docs::{doc::{document_id, Doc, DocMetadata}, encoding::normalize, markdown::{collapse_whitespace, render_heading, render_table}};
+
+/// Structural elements shared by DOCX and ODT documents.
+#[derive(Debug)]
+enum Block {
+    Heading(usize, String),
+    Paragraph(String),
+    ListItem(usize, String),
+    Table(Vec<Vec<String>>),
+}
+
+pub fn load_docx(path: &Path, relative_path: String) -> Result<Doc> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let styles = match read_archive_entry(&mut archive, "word/styles.xml") {
+        Ok(xml) => docx_heading_styles(&xml)?,
+        Err(_) => HashMap::new(),
+    };
+    let document = read_archive_entry(&mut archive, "word/document.xml")?;
+    let blocks = parse_docx(&document, &styles)?;
+    Ok(office_doc(path, relative_path, &blocks))
+}
+
+pub fn load_odt(path: &Path, relative_path: String) -> Result<Doc> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let content = read_archive_entry(&mut archive, "content.xml")?;
+    let blocks = parse_odt(&content)?;
+    Ok(office_doc(path, relative_path, &blocks))
+}
+
+fn office_doc(path: &Path, relative_path: String, blocks: &[Block]) -> Doc {
+    let content = normalize(&render_blocks(blocks));
+    Doc {
+        id: document_id(&relative_path, &content),
+        content,
+        file_name: path.to_string_lossy().to_string(),
+        relative_path,
+        pages: vec![],
+        metadata: DocMetadata::default(),
+    }
+}
+
+fn read_archive_entry(archive: &mut ZipArchive<File>, name: &str) -> Result<String> {
+    let mut entry = archive
+        .by_name(name)
+        .map_err(|e| Error::msg(format!("Missing {} in archive: {}", name, e)))?;
+    let mut contents = String::new();
+    entry.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+fn attribute(element: &BytesStart, name: &str) -> Option<String> {
+    element
+        .try_get_attribute(name)
+        .ok()
+        .flatten()
+        // Not `unescape_value`, which is missing when quick-xml's `encoding` feature is enabled (by calamine)
+        .and_then(|a| unescape(std::str::from_utf8(&a.value).ok()?).ok().map(|v| v.to_string()))
+}
+
+/// Maps DOCX style ids to heading levels. Style ids are localized
+/// (e.g. `Naslov1` in Slovene Word), but style names are not.
+fn docx_heading_styles(xml: &str) -> Result<HashMap<String, usize>> {
+    let mut reader = Reader::from_str(xml);
+    let mut styles = HashMap::new();
+    let mut current_style: Option<String> = None;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) if e.name().as_ref() == b"w:style" => {
+                current_style = attribute(&e, "w:styleId");
+            },
+            Event::Empty(e) if e.name().as_ref() == b"w:name" => {
+                if let (Some(id), Some(name)) = (&current_style, attribute(&e, "w:val")) {
+                    if let Some(level) = heading_level(&name) {
+                        styles.insert(id.clone(), level);
+                    }
+                }
+            },
+            Event::End(e) if e.name().as_ref() == b"w:style" => current_style = None,
+            Event::Eof => break,
+            _ => (),
+        }
+    }
+    Ok(styles)
+}
+
+fn heading_level(style: &str) -> Option<usize> {
+    let style = style.to_lowercase().replace(' ', "");
+    if style == "title" {
+        return Some(1);
+    }
+    ["heading", "naslov"]
+        .iter()
+        .find_map(|prefix| style.strip_prefix(prefix))
+        .and_then(|level| level.parse::<usize>().ok())
+}
+
+#[derive(Default)]
+struct DocxParagraph {
+    text: String,
+    heading: Option<usize>,
+    list_level: Option<usize>,
+}
+
+fn parse_docx(xml: &str, styles: &HashMap<String, usize>) -> Result<Vec<Block>> {
+    let mut reader = Reader::from_str(xml);
+    let mut blocks = vec![];
+    let mut paragraph = DocxParagraph::default();
+    let mut in_text = false;
+    let mut table_depth = 0;
+    let mut rows: Vec<Vec<String>> = vec![];
+    let mut cell = String::new();
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => match e.name().as_ref() {
+                b"w:p" => paragraph = DocxParagraph::default(),
+                b"w:t" => in_text = true,
+                b"w:tbl" => {
+                    table_depth += 1;
+                    if table_depth == 1 {
+                        rows.clear();
+                    }
+                },
+                b"w:tr" if table_depth == 1 => rows.push(vec![]),
+                b"w:tc" if table_depth == 1 => cell.clear(),
+                b"w:numPr" => paragraph.list_level = Some(paragraph.list_level.unwrap_or(0)),
+                _ => (),
+            },
+            Event::Empty(e) => match e.name().as_ref() {
+                b"w:pStyle" => {
+                    if let Some(level) = attribute(&e, "w:val").and_then(|id| styles.get(&id).copied().or_else(|| heading_level(&id))) {
+                        paragraph.heading = Some(level);
+                    }
+                },
+                b"w:outlineLvl" => {
+                    if let Some(level) = attribute(&e, "w:val").and_then(|v| v.parse::<usize>().ok()) {
+                        // Level 9 means body text
+                        if level < 9 {
+                            paragraph.heading = Some(level + 1);
+                        }
+                    }
+                },
+                b"w:ilvl" => paragraph.list_level = attribute(&e, "w:val").and_then(|v| v.parse().ok()),
+                b"w:tab" => paragraph.text.push('\t'),
+                b"w:br" | b"w:cr" => paragraph.text.push('\n'),
+                _ => (),
+            },
+            Event::Text(t) if in_text => paragraph.text.push_str(&t.unescape()?),
+            Event::End(e) => match e.name().as_ref() {
+                b"w:t" => in_text = false,
+                b"w:p" => {
+                    let paragraph = std::mem::take(&mut paragraph);
+                    if table_depth > 0 {
+                        if !cell.is_empty() {
+                            cell.push(' ');
+                        }
+                        cell.push_str(&paragraph.text);
+                    } else {
+                        push_paragraph(&mut blocks, paragraph.text, paragraph.heading, paragraph.list_level);
+                    }
+                },
+                b"w:tc" if table_depth == 1 => {
+                    if let Some(row) = rows.last_mut() {
+                        row.push(collapse_whitespace(&cell));
+                    }
+                },
+                b"w:tbl" => {
+                    table_depth -= 1;
+                    if table_depth == 0 {
+                        blocks.push(Block::Table(std::mem::take(&mut rows)));
+                    }
+                },
+                _ => (),
+            },
+            Event::Eof => break,
+            _ => (),
+        }
+    }
+    Ok(blocks)
+}
+
+fn parse_odt(xml: &str) -> Result<Vec<Block>> {
+    let mut reader = Reader::from_str(xml);
+    let mut blocks = vec![];
+    let mut text = String::new();
+    let mut text_depth = 0;
+    let mut heading: Option<usize> = None;
+    let mut list_depth = 0;
+    let mut item = String::new();
+    let mut table_depth = 0;
+    let mut rows: Vec<Vec<String>> = vec![];
+    let mut cell = String::new();
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => match e.name().as_ref() {
+                b"text:h" => {
+                    heading = Some(attribute(&e, "text:outline-level").and_then(|l| l.parse().ok()).unwrap_or(1));
+                    text_depth += 1;
+                },
+                b"text:p" => text_depth += 1,
+                b"text:list" => {
+                    // The text of an item is emitted before its nested list
+                    if list_depth > 0 && table_depth == 0 {
+                        push_paragraph(&mut blocks, std::mem::take(&mut item), None, Some(list_depth - 1));
+                    }
+                    list_depth += 1;
+                },
+                b"table:table" => {
+                    table_depth += 1;
+                    if table_depth == 1 {
+                        rows.clear();
+                    }
+                },
+                b"table:table-row" if table_depth == 1 => rows.push(vec![]),
+                b"table:table-cell" if table_depth == 1 => cell.clear(),
+                _ => (),
+            },
+            Event::Empty(e) => match e.name().as_ref() {
+                b"text:s" if text_depth > 0 => {
+                    let count = attribute(&e, "text:c").and_then(|c| c.parse().ok()).unwrap_or(1);
+                    text.push_str(&" ".repeat(count));
+                },
+                b"text:tab" if text_depth > 0 => text.push('\t'),
+                b"text:line-break" if text_depth > 0 => text.push('\n'),
+                b"table:table-cell" | b"table:covered-table-cell" if table_depth == 1 => {
+                    if let Some(row) = rows.last_mut() {
+                        row.push(String::new());
+                    }
+                },
+                _ => (),
+            },
+            Event::Text(t) if text_depth > 0 => text.push_str(&t.unescape()?),
+            Event::End(e) => match e.name().as_ref() {
+                b"text:h" | b"text:p" => {
+                    text_depth -= 1;
+                    if text_depth > 0 {
+                        continue;
+                    }
+                    let paragraph = std::mem::take(&mut text);
+                    if table_depth > 0 {
+                        if !cell.is_empty() {
+                            cell.push(' ');
+                        }
+                        cell.push_str(&paragraph);
+                    } else if list_depth > 0 {
+                        if !item.is_empty() {
+                            item.push(' ');
+                        }
+                        item.push_str(&paragraph);
+                    } else {
+                        push_paragraph(&mut blocks, paragraph, heading.take(), None);
+                    }
+                    heading = None;
+                },
+                b"text:list-item" if table_depth == 0 => {
+                    push_paragraph(&mut blocks, std::mem::take(&mut item), None, Some(list_depth.saturating_sub(1)));
+                },
+                b"text:list" => list_depth -= 1,
+                b"table:table-cell" if table_depth == 1 => {
+                    if let Some(row) = rows.last_mut() {
+                        row.push(collapse_whitespace(&cell));
+                    }
+                },
+                b"table:table" => {
+                    table_depth -= 1;
+                    if table_depth == 0 {
+                        blocks.push(Block::Table(std::mem::take(&mut rows)));
+                    }
+                },
+                _ => (),
+            },
+            Event::Eof => break,
+            _ => (),
+        }
+    }
+    Ok(blocks)
+}
+
+fn push_paragraph(blocks: &mut Vec<Block>, text: String, heading: Option<usize>, list_level: Option<usize>) {
+    let text = text.trim().to_string();
+    if text.is_empty() {
+        return;
+    }
+    let block = match (heading, list_level) {
+        (Some(level), _) => Block::Heading(level, text),
+        (None, Some(level)) => Block::ListItem(level, text),
+        (None, None) => Block::Paragraph(text),
+    };
+    blocks.push(block);
+}
+
+fn render_blocks(blocks: &[Block]) -> String {
+    let mut rendered: Vec<String> = vec![];
+    let mut previous_was_item = false;
+
+    for block in blocks {
+        let is_item = matches!(block, Block::ListItem(..));
+        let text = match block {
+            Block::Heading(level, text) => render_heading(*level, text),
+            Block::Paragraph(text) => text.clone(),
+            Block::ListItem(level, text) => format!("{}- {}", "  ".repeat(*level), collapse_whitespace(text)),
+            Block::Table(rows) => render_table(rows),
+        };
+        if text.is_empty() {
+            continue;
+        }
+        // Consecutive list items form a single markdown block
+        match rendered.last_mut() {
+            Some(last) if is_item && previous_was_item => {
+                last.push('\n');
+                last.push_str(&text);
+            },
+            _ => rendered.push(text),
+        }
+        previous_was_item = is_item;
+    }
+
+    rendered.join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::{write::FileOptions, ZipWriter};
+
+    use super::*;
+
+    fn write_archive(name: &str, entries: &[(&str, &str)]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("{}_{}", std::process::id(), name));
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        for (entry, contents) in entries {
+            zip.start_file(*entry, FileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn docx_headings_lists_and_tables_become_markdown() {
+        let styles = r#"<w:styles>
+            <w:style w:styleId="Naslov1"><w:name w:val="heading 1"/></w:style>
+            <w:style w:styleId="Navaden"><w:name w:val="Normal"/></w:style>
+        </w:styles>"#;
+        let document = r#"<w:document><w:body>
+            <w:p><w:pPr><w:pStyle w:val="Naslov1"/></w:pPr><w:r><w:t>Pravilnik</w:t></w:r></w:p>
+            <w:p><w:r><w:t xml:space="preserve">Velja za </w:t></w:r><w:r><w:t>študente &amp; profesorje.</w:t></w:r></w:p>
+            <w:p><w:pPr><w:numPr><w:ilvl w:val="0"/></w:numPr></w:pPr><w:r><w:t>Prijava</w:t></w:r></w:p>
+            <w:p><w:pPr><w:numPr><w:ilvl w:val="1"/></w:numPr></w:pPr><w:r><w:t>Rok</w:t></w:r></w:p>
+            <w:tbl>
+                <w:tr><w:tc><w:p><w:r><w:t>Predmet</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>ECTS</w:t></w:r></w:p></w:tc></w:tr>
+                <w:tr><w:tc><w:p><w:r><w:t>Analiza</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>6</w:t></w:r></w:p></w:tc></w:tr>
+            </w:tbl>
+        </w:body></w:document>"#;
+        let path = write_archive("test.docx", &[("word/styles.xml", styles), ("word/document.xml", document)]);
+
+        let doc = load_docx(&path, "test.docx".to_string()).unwrap();
+        assert_eq!(doc.content, "# Pravilnik\n\nVelja za študente & profesorje.\n\n- Prijava\n  - Rok\n\n| Predmet | ECTS |\n| --- | --- |\n| Analiza | 6 |");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn odt_headings_lists_and_tables_become_markdown() {
+        let content = r#"<office:document-content><office:body><office:text>
+            <text:h text:outline-level="2">Vpis</text:h>
+            <text:p>Prvi<text:s text:c="2"/>odstavek</text:p>
+            <text:list>
+                <text:list-item><text:p>Prijava</text:p>
+                    <text:list><text:list-item><text:p>Rok</text:p></text:list-item></text:list>
+                </text:list-item>
+                <text:list-item><text:p>Vpis</text:p></text:list-item>
+            </text:list>
+            <table:table>
+                <table:table-row><table:table-cell><text:p>Predmet</text:p></table:table-cell><table:table-cell/></table:table-row>
+                <table:table-row><table:table-cell><text:p>Analiza</text:p></table:table-cell><table:table-cell><text:p>6</text:p></table:table-cell></table:table-row>
+            </table:table>
+        </office:text></office:body></office:document-content>"#;
+        let path = write_archive("test.odt", &[("content.xml", content)]);
+
+        let doc = load_odt(&path, "test.odt".to_string()).unwrap();
+        assert_eq!(doc.content, "## Vpis\n\nPrvi  odstavek\n\n- Prijava\n  - Rok\n- Vpis\n\n| Predmet |  |\n| --- | --- |\n| Analiza | 6 |");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn archives_without_a_document_are_errors() {
+        let path = write_archive("empty.docx", &[("word/styles.xml", "<w:styles/>")]);
+        assert!(load_docx(&path, "empty.docx".to_string()).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
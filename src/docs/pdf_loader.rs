@@ -0,0 +1,142 @@
+use std::{collections::HashMap, panic, path::Path};
+
+use anyhow::{Error, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::docs::{doc::{document_id, Doc, DocMetadata, PageSpan}, encoding::normalize};
+
+pub fn load_pdf(path: &Path, relative_path: String) -> Result<Doc> {
+    // pdf-extract panics on some malformed fonts instead of returning an error
+    let owned_path = path.to_path_buf();
+    let pages = match panic::catch_unwind(move || pdf_extract::extract_text_by_pages(owned_path)) {
+        Ok(result) => result?,
+        Err(_) => return Err(Error::msg(format!("PDF extraction panicked for {}", path.display()))),
+    };
+
+    let (content, spans) = join_pages(strip_running_lines(pages));
+    Ok(Doc {
+        id: document_id(&relative_path, &content),
+        content,
+        file_name: path.to_string_lossy().to_string(),
+        relative_path,
+        pages: spans,
+        metadata: DocMetadata::default(),
+    })
+}
+
+/// Joins page texts with `"\n\n"` and records the byte range each page ended up at.
+/// Empty pages are skipped but keep their page number.
+pub fn join_pages(pages: Vec<String>) -> (String, Vec<PageSpan>) {
+    let mut content = String::new();
+    let mut spans = vec![];
+
+    for (index, page) in pages.into_iter().enumerate() {
+        let text = normalize(&page.replace("\r\n", "\n").replace('\u{c}', ""));
+        let text = repair_hyphenation(&text);
+        let text = text.trim();
+        if text.is_empty() {
+            continue;
+        }
+        if !content.is_empty() {
+            content.push_str("\n\n");
+        }
+        let start = content.len();
+        content.push_str(text);
+        spans.push(PageSpan {
+            page: index + 1,
+            start,
+            end: content.len(),
+        });
+    }
+
+    (content, spans)
+}
+
+// A lowercase letter, a hyphen at the end of the line and a lowercase letter on the next one
+static LINE_END_HYPHEN: Lazy<Regex> = Lazy::new(|| Regex::new(r"(\p{Ll})-\n[ \t]*(\p{Ll})").unwrap());
+
+/// Joins words hyphenated across line ends (`pravil-\nnik` to `pravilnik`).
+fn repair_hyphenation(text: &str) -> String {
+    LINE_END_HYPHEN.replace_all(text, "$1$2").into_owned()
+}
+
+/// Drops running headers and footers: first or last lines repeated on more than half of the pages.
+/// Digits are ignored when comparing lines, so page numbers (`Stran 3 od 12`) still match.
+pub fn strip_running_lines(pages: Vec<String>) -> Vec<String> {
+    if pages.len() < 3 {
+        return pages;
+    }
+    let shape = |line: &str| line.trim().replace(|c: char| c.is_ascii_digit(), "#");
+    let running = |edge: fn(&String) -> Option<&str>| {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for line in pages.iter().filter_map(edge) {
+            *counts.entry(shape(line)).or_default() += 1;
+        }
+        counts.retain(|_, count| *count * 2 > pages.len());
+        counts
+    };
+    let headers = running(|page| page.lines().find(|l| !l.trim().is_empty()));
+    let footers = running(|page| page.lines().rev().find(|l| !l.trim().is_empty()));
+
+    pages.iter()
+        .map(|page| {
+            let mut lines: Vec<&str> = page.lines().collect();
+            if let Some(first) = lines.iter().position(|l| !l.trim().is_empty()) {
+                if headers.contains_key(&shape(lines[first])) {
+                    lines.remove(first);
+                }
+            }
+            if let Some(last) = lines.iter().rposition(|l| !l.trim().is_empty()) {
+                if footers.contains_key(&shape(lines[last])) {
+                    lines.remove(last);
+                }
+            }
+            lines.join("\n")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_are_joined_with_their_spans() {
+        let pages = vec!["Prva stran\r\n".to_string(), "\u{c}  ".to_string(), "Tretja stran".to_string()];
+        let (content, spans) = join_pages(pages);
+
+        assert_eq!(content, "Prva stran\n\nTretja stran");
+        assert_eq!(spans, vec![
+            PageSpan { page: 1, start: 0, end: 10 },
+            PageSpan { page: 3, start: 12, end: 24 },
+        ]);
+        assert_eq!(&content[spans[1].start..spans[1].end], "Tretja stran");
+    }
+
+    #[test]
+    fn words_hyphenated_across_lines_are_joined() {
+        let (content, _) = join_pages(vec!["Ta pravil-\nnik velja za štu-\n  dente.\nUP-\nFAMNIT".to_string()]);
+        assert_eq!(content, "Ta pravilnik velja za študente.\nUP-\nFAMNIT");
+    }
+
+    #[test]
+    fn running_headers_and_footers_are_stripped() {
+        let pages: Vec<String> = (1..=4)
+            .map(|n| format!("Univerza na Primorskem\n\n{n}. člen besedilo\n\nStran {n} od 4\n"))
+            .collect();
+        let stripped = strip_running_lines(pages);
+
+        assert_eq!(stripped[0], "\n1. člen besedilo\n");
+        assert_eq!(stripped[3], "\n4. člen besedilo\n");
+    }
+
+    #[test]
+    fn lines_on_few_pages_are_kept() {
+        let pages = vec!["Uvod\nbesedilo".to_string(), "Uvod\nbesedilo".to_string()];
+        assert_eq!(strip_running_lines(pages.clone()), pages);
+
+        let pages = vec!["A\nx".to_string(), "B\ny".to_string(), "C\nz".to_string()];
+        assert_eq!(strip_running_lines(pages.clone()), pages);
+    }
+}
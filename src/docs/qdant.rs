@@ -1,12 +1,12 @@
 use candle_core::Tensor;
 use once_cell::sync::Lazy;
-use qdrant_client::{client::QdrantClient, qdrant::{PointStruct, SearchPoints, SearchResponse}};
+use qdrant_client::{client::QdrantClient, qdrant::{PointId, PointStruct, SearchPoints, SearchResponse}};
 use serde_json::json;
 use tokio::sync::Mutex;
 use anyhow::{Error, Result};
-use crate::{config::{QDRANT_COLLECTION, QDRANT_SERVER}, util::get_progress_bar};
+use crate::{config::config, util::get_progress_bar};
 
-use super::embedded_doc::EmbeddedDoc;
+use super::{doc::document_id, embedded_doc::EmbeddedDoc};
 
 /// Static global client for accessing the Qdrant database.
 ///
@@ -16,7 +16,7 @@ use super::embedded_doc::EmbeddedDoc;
 /// # Panics
 /// - Panics if the connection to the Qdrant database cannot be established, indicating a configuration or network issue.
 static QDRANT_CLIENT: Lazy<Mutex<QdrantClient>> = Lazy::new(|| {
-    let client = match QdrantClient::from_url(QDRANT_SERVER).build() {
+    let client = match QdrantClient::from_url(&config().qdrant.server).build() {
         Ok(c) => c,
         Err(e) => panic!("Can't establish Qdrant DB connection: {:#?}", e),
     };
@@ -43,7 +43,7 @@ pub async fn vector_search(embedding: Tensor) -> Result<SearchResponse> {
     let guard = QDRANT_CLIENT.lock().await;
     let search_result = guard
         .search_points(&SearchPoints {
-            collection_name: QDRANT_COLLECTION.to_string(),
+            collection_name: config().qdrant.collection.clone(),
             vector: embedding_vec,
             limit: 2,
             with_payload: Some(true.into()),
@@ -54,6 +54,19 @@ pub async fn vector_search(embedding: Tensor) -> Result<SearchResponse> {
     Ok(search_result)
 }
 
+/// Upserts embedded passages into the configured Qdrant collection.
+///
+/// Points are keyed by `EmbeddedDoc::id`, so inserting the same passages again overwrites
+/// the existing points instead of adding duplicates.
+///
+/// # Parameters
+/// - `embedded_docs`: The passages to upsert, with their embedding vectors.
+///
+/// # Returns
+/// Returns `Ok(())` once every passage has been upserted.
+///
+/// # Errors
+/// - Returns an error on the first point Qdrant fails to upsert.
 pub async fn insert_docs(embedded_docs: Vec<EmbeddedDoc>) -> Result<()> {
     println!("Upserting to qdrant...");
     let guard = QDRANT_CLIENT.lock().await;
@@ -61,10 +74,10 @@ pub async fn insert_docs(embedded_docs: Vec<EmbeddedDoc>) -> Result<()> {
 
     for doc in embedded_docs {
         let insert_result = guard.upsert_points(
-            QDRANT_COLLECTION.to_string(), // The name of the collection
+            config().qdrant.collection.clone(), // The name of the collection
             None,
             vec![PointStruct::new(
-                uuid::Uuid::new_v4().to_string(),
+                point_id(&doc),
                 doc.vector,
                 json!(doc.content)
                 .try_into()
@@ -79,5 +92,36 @@ pub async fn insert_docs(embedded_docs: Vec<EmbeddedDoc>) -> Result<()> {
     Ok(())
 }
 
+/// Deletes points from the configured Qdrant collection.
+///
+/// # Parameters
+/// - `ids`: IDs of the points to delete. Unknown IDs are ignored by Qdrant.
+///
+/// # Returns
+/// Returns `Ok(())` once the points are deleted, or right away if `ids` is empty.
+///
+/// # Errors
+/// - Returns an error if the Qdrant delete request fails.
+pub async fn delete_points(ids: Vec<String>) -> Result<()> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+    println!("Deleting {} stale points from qdrant...", ids.len());
+    let guard = QDRANT_CLIENT.lock().await;
+    let points: Vec<PointId> = ids.into_iter().map(PointId::from).collect();
+    guard
+        .delete_points(config().qdrant.collection.clone(), None, &points.into(), None)
+        .await
+        .map_err(|e| Error::msg(format!("Failed to delete points: {}", e)))?;
+    Ok(())
+}
 
-
+/// Falls back to an ID derived from the passage itself for records saved
+/// before passages carried their own ID.
+fn point_id(doc: &EmbeddedDoc) -> String {
+    if doc.id.is_empty() {
+        document_id(&doc.content.source, &doc.content.text)
+    } else {
+        doc.id.clone()
+    }
+}
@@ -1,8 +1,9 @@
 use std::{fs::{File, OpenOptions}, io::Write, path::Path};
 use anyhow::Result;
 use csv::Writer;
+use serde::Serialize;
 
-use crate::{controllers::translator::ProcessedDocumentChunk, util::get_progress_bar};
+use crate::{docs::chunk::ProcessedDocumentChunk, util::get_progress_bar};
 
 
 pub fn save_to_csv(records: Vec<ProcessedDocumentChunk>, file_name: &str) -> Result<()> {
@@ -12,7 +13,7 @@ pub fn save_to_csv(records: Vec<ProcessedDocumentChunk>, file_name: &str) -> Res
     println!("Saving file: {}", file_name);
     let progress_bar = get_progress_bar(records.len(), 2);
     for record in records.into_iter() {
-        match wtr.write_record(&[record.0, record.1]) {
+        match wtr.write_record(&[record.input, record.output]) {
             Ok(_) => successful_writes += 1,
             Err(_) => failed_writes += 1,
         };
@@ -34,15 +35,49 @@ pub fn save_to_json(records: &Vec<ProcessedDocumentChunk>, file_name: &str) -> R
     };
 
     for record in records {
-        if let Err(e) = writeln!(file, "{:?}", serde_json::to_string(record).unwrap()) {
+        if let Err(e) = writeln!(file, "{}", json_line(record)?) {
             eprintln!("Couldn't write to file: {}", e);
         }
     }
     Ok(())
 }
 
+/// Formats a record the way `save_to_json` writes it, as one JSON object per line.
+pub fn json_line(record: &ProcessedDocumentChunk) -> Result<String> {
+    Ok(serde_json::to_string(record)?)
+}
+
+pub fn save_jsonl<T: Serialize>(records: &[T], file_name: &str) -> Result<()> {
+    let mut file = File::create(file_name)?;
+    for record in records {
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+    }
+    Ok(())
+}
+
 pub fn save_raw(content: String, file_name: String) -> Result<()> {
     let mut f = File::create(&file_name)?;
     f.write_all(content.as_bytes())?;
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::docs::chunk::Provenance;
+
+    use super::*;
+
+    #[test]
+    fn json_lines_are_json_objects() {
+        let record = ProcessedDocumentChunk {
+            input: "Vpis \"poteka\"\njulija.".to_string(),
+            output: "KW: vpis".to_string(),
+            success: true,
+            provenance: Provenance { source: "a.md".to_string(), index: 2, ..Default::default() },
+        };
+        let line = json_line(&record).unwrap();
+        assert!(!line.contains('\n'));
+        let parsed: ProcessedDocumentChunk = serde_json::from_str(&line).unwrap();
+        assert_eq!((parsed.input, parsed.provenance.index), (record.input, 2));
+    }
+}
@@ -0,0 +1,186 @@
+use std::path::Path;
+
+use anyhow::{Error, Result};
+use calamine::{open_workbook_auto, Reader};
+use csv::ReaderBuilder;
+
+use crate::{config::{config, TableConfig}, docs::{doc::{row_id, Doc, DocMetadata}, loader::build_globset}};
+
+const DELIMITERS: [u8; 3] = [b',', b';', b'\t'];
+
+/// Turns every row of a CSV file into a document. `content` is the decoded file.
+pub fn load_csv(path: &Path, relative_path: &str, content: &str) -> Result<Vec<Doc>> {
+    let settings = table_settings(relative_path)?;
+    let delimiter = match settings.delimiter {
+        Some(d) => d as u8,
+        None => sniff_delimiter(content),
+    };
+
+    let mut reader = ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(content.as_bytes());
+    let headers: Vec<String> = reader.headers()?.iter().map(|h| h.trim().to_string()).collect();
+    let mut rows = vec![];
+    for record in reader.records() {
+        rows.push(record?.iter().map(|c| c.trim().to_string()).collect());
+    }
+
+    Ok(rows_to_docs(path, relative_path, &settings, None, &headers, rows))
+}
+
+/// Turns every row of every sheet (or only `sheet`, if configured) of a spreadsheet into a document.
+pub fn load_xlsx(path: &Path, relative_path: &str) -> Result<Vec<Doc>> {
+    let settings = table_settings(relative_path)?;
+    let mut workbook = open_workbook_auto(path)?;
+    let sheets = match &settings.sheet {
+        Some(sheet) => vec![sheet.clone()],
+        None => workbook.sheet_names(),
+    };
+
+    let mut docs = vec![];
+    for sheet in sheets {
+        let range = workbook
+            .worksheet_range(&sheet)
+            .map_err(|e| Error::msg(format!("Can't read sheet '{}': {}", sheet, e)))?;
+        let mut rows = range
+            .rows()
+            .map(|row| row.iter().map(|c| c.to_string().trim().to_string()).collect::<Vec<String>>());
+        let headers = match rows.next() {
+            Some(h) => h,
+            None => continue,
+        };
+        docs.extend(rows_to_docs(path, relative_path, &settings, Some(&sheet), &headers, rows.collect()));
+    }
+    Ok(docs)
+}
+
+/// Returns the first `[[loader.tables]]` entry whose glob matches the file.
+fn table_settings(relative_path: &str) -> Result<TableConfig> {
+    for table in &config().loader.tables {
+        if build_globset(std::slice::from_ref(&table.files))?.is_match(relative_path) {
+            return Ok(table.clone());
+        }
+    }
+    Ok(TableConfig::default())
+}
+
+fn rows_to_docs(
+    path: &Path,
+    relative_path: &str,
+    settings: &TableConfig,
+    sheet: Option<&str>,
+    headers: &[String],
+    rows: Vec<Vec<String>>,
+) -> Vec<Doc> {
+    let key_index = settings
+        .key_column
+        .as_ref()
+        .and_then(|key| headers.iter().position(|h| h == key));
+    if let (Some(key), None) = (&settings.key_column, key_index) {
+        println!("Key column '{}' not found in {}, using row numbers", key, relative_path);
+    }
+
+    rows.into_iter()
+        .enumerate()
+        .filter(|(_, row)| row.iter().any(|c| !c.is_empty()))
+        .map(|(index, row)| {
+            let key = match key_index.and_then(|i| row.get(i)).filter(|k| !k.is_empty()) {
+                Some(k) => k.clone(),
+                // Header is line 1
+                None => (index + 2).to_string(),
+            };
+            let key = match sheet {
+                Some(s) => format!("{}/{}", s, key),
+                None => key,
+            };
+            let content = match &settings.template {
+                Some(template) => render_template(template, headers, &row),
+                None => render_fields(headers, &row),
+            };
+            Doc {
+                id: row_id(relative_path, &key),
+                content,
+                // Keeps the outputs of rows apart, e.g. the per document files of `translate`
+                file_name: format!("{}#{}", path.to_string_lossy(), key),
+                relative_path: relative_path.to_string(),
+                pages: vec![],
+                metadata: DocMetadata::default(),
+            }
+        })
+        .collect()
+}
+
+/// Replaces `{column}` placeholders with the values of the row.
+fn render_template(template: &str, headers: &[String], row: &[String]) -> String {
+    let mut rendered = template.to_string();
+    for (header, value) in headers.iter().zip(row) {
+        rendered = rendered.replace(&format!("{{{}}}", header), value);
+    }
+    rendered
+}
+
+/// Fallback without a template: a `column: value` line per non-empty cell.
+fn render_fields(headers: &[String], row: &[String]) -> String {
+    headers
+        .iter()
+        .zip(row)
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(header, value)| format!("{}: {}", header, value))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Picks the delimiter that splits the header line into the most columns.
+fn sniff_delimiter(content: &str) -> u8 {
+    let header = content.lines().next().unwrap_or("");
+    DELIMITERS
+        .iter()
+        .copied()
+        .filter(|d| header.contains(*d as char))
+        .max_by_key(|d| header.matches(*d as char).count())
+        .unwrap_or(b',')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn rows_become_field_lines() {
+        let docs = load_csv(Path::new("in/predmeti.csv"), "predmeti.csv", "ime;ects\nMatematika;6\n;\nFizika;").unwrap();
+        assert_eq!(docs.len(), 2);
+        assert_eq!(docs[0].content, "ime: Matematika\nects: 6");
+        // Empty cells are left out, empty rows skipped, keys are line numbers
+        assert_eq!(docs[1].content, "ime: Fizika");
+        assert_eq!(docs[1].file_name, "in/predmeti.csv#4");
+        assert_eq!(docs[1].id, row_id("predmeti.csv", "4"));
+    }
+
+    #[test]
+    fn templates_and_key_columns() {
+        let settings = TableConfig {
+            template: Some("Predmet {ime} ima {ects} ECTS".to_string()),
+            key_column: Some("sifra".to_string()),
+            ..Default::default()
+        };
+        let headers = strings(&["sifra", "ime", "ects"]);
+        let rows = vec![strings(&["M1", "Matematika", "6"]), strings(&["", "Fizika", "5"])];
+        let docs = rows_to_docs(Path::new("p.xlsx"), "p.xlsx", &settings, Some("List1"), &headers, rows);
+        assert_eq!(docs[0].content, "Predmet Matematika ima 6 ECTS");
+        assert_eq!(docs[0].file_name, "p.xlsx#List1/M1");
+        // Rows without a key fall back to their line number
+        assert_eq!(docs[1].file_name, "p.xlsx#List1/3");
+    }
+
+    #[test]
+    fn delimiter_splits_the_header_into_most_columns() {
+        assert_eq!(sniff_delimiter("a;b;c\n1,2;3"), b';');
+        assert_eq!(sniff_delimiter("a\tb\n"), b'\t');
+        assert_eq!(sniff_delimiter("a"), b',');
+    }
+}
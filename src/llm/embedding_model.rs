@@ -1,59 +1,37 @@
 use anyhow::{Error, Result};
 use candle_core::{Device, Tensor};
 use candle_nn::rotary_emb::{self, rope};
+use candle_transformers::models::bert::BertModel;
+use tokenizers::Tokenizer;
 
-use crate::llm::loader::load_bert_model;
-
-
-/// Generates a normalized L2 embedding for a given text prompt using a pre-loaded BERT model.
+/// Generates a normalized embedding for a text with an already loaded BERT model.
 ///
-/// This function loads a BERT model and tokenizer, encodes the input prompt into token IDs, and uses the model
-/// to generate word embeddings. The embeddings are then pooled (averaged) and normalized to produce a single
-/// vector representation of the prompt, suitable for further processing or similarity comparisons.
+/// The token embeddings are mean pooled and L2 normalized. Callers load the model once with
+/// `load_bert_model` and reuse it for every text, as loading it takes far longer than a forward pass.
 ///
 /// # Parameters
-/// - `prompt`: The text prompt to be embedded.
+/// - `model`, `tokenizer`, `device`: The model as returned by `load_bert_model`.
+/// - `text`: The text to embed.
+/// - `max_tokens`: The model's input length. Longer texts are truncated.
 ///
 /// # Returns
-/// Returns a `Result` containing the normalized tensor representing the L2 embedding of the prompt.
+/// Returns a `Result` containing the embedding vector.
 ///
 /// # Errors
-/// - Returns an error if any step in the embedding generation process fails, including model loading, tokenization,
-///   tensor operations, or WebSocket communication failures.
-pub async fn embedd(
-    prompt: &str,
-) -> Result<Tensor> {
-    let (model, tokenizer, device) = load_bert_model(Some(0))?;
-    let tokens = tokenizer
-        .encode(prompt, true)
+/// - Returns an error if tokenization or any tensor operation fails.
+pub fn embedd_with(model: &BertModel, tokenizer: &Tokenizer, device: &Device, text: &str, max_tokens: usize) -> Result<Vec<f32>> {
+    let mut tokens = tokenizer
+        .encode(text, true)
         .map_err(Error::msg)?
         .get_ids()
         .to_vec();
-    let token_ids = Tensor::new(&tokens[..], &device)?.unsqueeze(0)?;
+    tokens.truncate(max_tokens);
+    let token_ids = Tensor::new(&tokens[..], device)?.unsqueeze(0)?;
     let token_type_ids = token_ids.zeros_like()?;
     let embeddings = model.forward(&token_ids, &token_type_ids)?;
     let (_n_sentence, n_tokens, _hidden_size) = embeddings.dims3()?;
     let embeddings = (embeddings.sum(1)? / (n_tokens as f64))?;
-    
-    let embeddings = normalize_l2(&embeddings)?;
-    
-    
-    // ROTATY POSITION EMBEDINGS ATTEMPT
-    // // Compute Rotary Embeddings based on the sequence length and embedding dimension.
-    // let seq_len = tokens.len();
-    // let d_model = 1024;  // Assuming embeddings shape is [1, 1, seq_len, d_model]
-    // let (cos, sin) = generate_rotary_embeddings_for_sequence(
-    //     seq_len, 
-    //     d_model / 2, 
-    //     &device
-    // )?;
-    // let embeddings = embeddings.unsqueeze(0)?;
-    // let rotary_embedding = rope(&embeddings, &cos, &sin)?;
-    // let rotary_embedding = rotary_embedding.squeeze(0)?;
-    // // let rotary_embedding = (rotary_embedding.sum(1)? / (d_model as f64))?;
-    // let pooled_embedding = rotary_embedding.mean(1)?;  // Reduces across the sequence length, resulting in [1, d_model]
-    // let normalized_embedding = normalize_l2(&pooled_embedding)?;
-    Ok(embeddings)
+    Ok(normalize_l2(&embeddings)?.squeeze(0)?.to_vec1::<f32>()?)
 }
 
 fn generate_rotary_embeddings_for_sequence(seq_len: usize, half_d_model: usize, device: &Device) -> Result<(Tensor, Tensor)> {
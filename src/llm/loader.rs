@@ -6,7 +6,7 @@ use candle_nn::VarBuilder;
 use candle_transformers::models::bert::{BertModel, Config, DTYPE};
 use tokenizers::Tokenizer;
 
-use crate::config::EMBEDDING_MODEL_PATH;
+use crate::config::config;
 
 pub type LoadedEmbeddingModel = (BertModel, Tokenizer, Device);
 
@@ -27,11 +27,12 @@ pub type LoadedEmbeddingModel = (BertModel, Tokenizer, Device);
 ///   or incorrect model/tokenizer configurations.
 pub fn load_bert_model(gpu_id: Option<usize>) -> Result<LoadedEmbeddingModel> {
     let device = load_device(gpu_id);
-    let model = match load_pybin_bert_model_from_disk(EMBEDDING_MODEL_PATH, &device) { 
+    let model_path = &config().embedding.model_path;
+    let model = match load_pybin_bert_model_from_disk(model_path, &device) { 
         Ok(m) => m,
         Err(e) => panic!("Can't load embedding model: {:#?}", e),
     };
-    let tokenizer = match load_tokenizer(&format!("{}/tokenizer.json", EMBEDDING_MODEL_PATH)) {
+    let tokenizer = match load_tokenizer(&format!("{}/tokenizer.json", model_path)) {
         Ok(t) => t,
         Err(e) => panic!("Can't load tokenizer: {:#?}", e),
     };
@@ -39,6 +40,62 @@ pub fn load_bert_model(gpu_id: Option<usize>) -> Result<LoadedEmbeddingModel> {
 }
 
 
+/// Picks the device for the embedding model from `embedding.device`.
+///
+/// The embedding model shares the GPUs with the LLMs of a command when the semantic splitter is used,
+/// so it can be moved to another GPU or to the CPU.
+///
+/// # Returns
+/// Returns the GPU ID for `load_bert_model`, `None` for the CPU. Unknown values fall back to the CPU.
+pub fn embedding_gpu() -> Option<usize> {
+    let device = config().embedding.device.trim().to_lowercase();
+    if device == "cpu" {
+        return None;
+    }
+    match device.strip_prefix("cuda:").and_then(|id| id.parse().ok()) {
+        Some(id) => Some(id),
+        None => {
+            println!("Unknown embedding device '{}', using the CPU", device);
+            None
+        },
+    }
+}
+
+
+/// Loads the tokenizer of the embedding model configured in `embedding.model_path`.
+///
+/// Chunks meant for embedding should be measured with this tokenizer, as its vocabulary
+/// usually differs from the one of the LLM used for prompting.
+///
+/// # Returns
+/// Returns a `Result` containing the `Tokenizer` read from `<model_path>/tokenizer.json`.
+///
+/// # Errors
+/// - Returns an error if the tokenizer file can't be read or parsed.
+pub fn load_embedding_tokenizer() -> Result<Tokenizer> {
+    load_tokenizer(&format!("{}/tokenizer.json", config().embedding.model_path))
+}
+
+/// Reads the maximum input length (in tokens, special tokens included) of the embedding model.
+///
+/// The value is `max_position_embeddings` from the model's `config.json`, e.g. 512 for BERT/BGE models.
+///
+/// # Returns
+/// Returns a `Result` containing the maximum number of tokens the model accepts.
+///
+/// # Errors
+/// - Returns an error if the config file can't be read, isn't valid JSON or lacks `max_position_embeddings`.
+pub fn load_embedding_max_tokens() -> Result<usize> {
+    let config_path = format!("{}/config.json", config().embedding.model_path);
+    let model_config: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&config_path)?)?;
+    model_config
+        .get("max_position_embeddings")
+        .and_then(|v| v.as_u64())
+        .map(|v| v as usize)
+        .ok_or_else(|| Error::msg(format!("No max_position_embeddings in {}", config_path)))
+}
+
+
 /// Initializes a computational device based on the provided GPU identifier.
 ///
 /// This function selects a computational device for running operations. If a valid GPU ID is provided
@@ -55,7 +112,7 @@ pub fn load_bert_model(gpu_id: Option<usize>) -> Result<LoadedEmbeddingModel> {
 /// # Notes
 /// * The function handles errors internally by logging them and falling back to CPU usage.
 /// * This approach ensures that the application can continue running even if CUDA is not available.
-fn load_device(gpu_id: Option<usize>) -> Device {
+pub fn load_device(gpu_id: Option<usize>) -> Device {
     if let Some(id) = gpu_id {
         match Device::new_cuda(id) {
             Ok(cuda) => cuda,
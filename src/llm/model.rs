@@ -1,8 +1,48 @@
+use std::sync::{Mutex, MutexGuard};
 use candle_core::{quantized::gguf_file::Content, Device};
 use candle_transformers::{generation::{LogitsProcessor, Sampling}, models::quantized_llama::ModelWeights};
 use anyhow::Result;
 
-use crate::config::{SEED, TEMPERATURE, TOP_K, TOP_P};
+use crate::{config::config, llm::loader::load_device};
+
+/// Number of GPUs the controllers spread their work across.
+pub const GPU_COUNT: usize = 2;
+
+/// A set of model copies, one per GPU, that can be shared across worker threads.
+pub struct ModelPair {
+    models: Vec<(Mutex<ModelWeights>, Device)>,
+}
+
+impl ModelPair {
+    /// Loads one copy of the model on each GPU, falling back to the CPU where CUDA is not available.
+    ///
+    /// # Arguments
+    /// * `model_path` - A string slice that specifies the path to the model file.
+    ///
+    /// # Returns
+    /// A `Result` containing the loaded models or an error if any of the copies fails to load.
+    pub fn load(model_path: &str) -> Result<Self> {
+        let mut models = vec![];
+        for gpu_id in 0..GPU_COUNT {
+            let device = load_device(Some(gpu_id));
+            let model = load_model(model_path, &device)?;
+            models.push((Mutex::new(model), device));
+        }
+        Ok(Self { models })
+    }
+
+    /// Locks the model assigned to the given worker index.
+    ///
+    /// # Arguments
+    /// * `index` - Index of the worker. Workers are assigned to models in a round robin fashion.
+    ///
+    /// # Returns
+    /// A guard to the locked model and the device the model was loaded on.
+    pub fn get(&self, index: usize) -> (MutexGuard<'_, ModelWeights>, &Device) {
+        let (model, device) = &self.models[index % self.models.len()];
+        (model.lock().unwrap(), device)
+    }
+}
 
 /// Loads model weights from a file path on a specified device.
 ///
@@ -35,22 +75,24 @@ pub fn load_model(model_path: &str, device: &Device) -> Result<ModelWeights> {
 /// An instance of `LogitsProcessor` configured with a specific sampling strategy.
 pub fn setup_logit_procesing() -> LogitsProcessor {
     let sampling = setup_sampling();
-    LogitsProcessor::from_sampling(SEED, sampling)
+    LogitsProcessor::from_sampling(config().generation.seed, sampling)
 }
 
 /// Configures the sampling strategy based on predefined temperature and probability settings.
 ///
 /// # Returns
-/// A `Sampling` variant configured according to the global temperature, TOP_K, and TOP_P settings.
+/// A `Sampling` variant configured according to the configured temperature, top_k, and top_p settings.
 fn setup_sampling() -> Sampling {
-    if TEMPERATURE <= 0. {
+    let settings = &config().generation;
+    let temperature = settings.temperature;
+    if temperature <= 0. {
         Sampling::ArgMax
     } else {
-        match (TOP_K, TOP_P) {
-            (None, None) => Sampling::All { temperature: TEMPERATURE },
-            (Some(k), None) => Sampling::TopK { k, temperature: TEMPERATURE },
-            (None, Some(p)) => Sampling::TopP { p, temperature: TEMPERATURE },
-            (Some(k), Some(p)) => Sampling::TopKThenTopP { k, p,temperature:  TEMPERATURE },
+        match (settings.top_k, settings.top_p) {
+            (None, None) => Sampling::All { temperature },
+            (Some(k), None) => Sampling::TopK { k, temperature },
+            (None, Some(p)) => Sampling::TopP { p, temperature },
+            (Some(k), Some(p)) => Sampling::TopKThenTopP { k, p, temperature },
         }
     }
 }
\ No newline at end of file
@@ -5,9 +5,7 @@ use candle_core::{Device, Tensor};
 use candle_transformers::models::quantized_llama::{ModelWeights, MAX_SEQ_LEN};
 use tokenizers::Tokenizer;
 
-use crate::{config::{
-    REPEAT_LAST_N, REPEAT_PENALTY, SAMPLE_LEN, SPLIT_PROPMT, VERBOSE_PROMPT
-}, llm::model::setup_logit_procesing};
+use crate::{config::config, llm::model::setup_logit_procesing};
 
 use super::tokenizer::TokenOutputStream;
 
@@ -79,12 +77,13 @@ pub fn prompt_model(
     prompt: Prompt, 
     device: &Device
 ) -> Result<String> {
+    let settings = &config().generation;
     let mut response_chunks = vec![];
     let mut tos = TokenOutputStream::new(tokenizer.clone());
    
     // Parse the prompt to a raw string format.
     let prompt_str = parse_prompt_to_raw(&prompt)?;
-    if VERBOSE_PROMPT {
+    if settings.verbose_prompt {
         print!("{}", &prompt_str);
     }
     
@@ -96,7 +95,7 @@ pub fn prompt_model(
         .map_err(anyhow::Error::msg)?;
     
     // Optionally, print each token and its ID if verbose logging is enabled.
-    if VERBOSE_PROMPT {
+    if settings.verbose_prompt {
         for (token, id) in tokens.get_tokens().iter().zip(tokens.get_ids().iter()) {
             let token = token.replace('▁', " ").replace("<0x0A>", "\n");
             println!("{id:7} -> '{token}'");
@@ -105,7 +104,7 @@ pub fn prompt_model(
     
     // Handle token length restrictions by trimming if necessary.
     let prompt_tokens = tokens.get_ids();
-    let to_sample = SAMPLE_LEN.saturating_sub(1);
+    let to_sample = settings.sample_len.saturating_sub(1);
     
     let prompt_tokens = if prompt_tokens.len() + to_sample > MAX_SEQ_LEN - 10 {
         let to_remove = prompt_tokens.len() + to_sample + 10 - MAX_SEQ_LEN;
@@ -119,7 +118,7 @@ pub fn prompt_model(
     let mut logits_processor = setup_logit_procesing();
 
     let start_prompt_processing: std::time::Instant = std::time::Instant::now();
-    let mut next_token = if !SPLIT_PROPMT {
+    let mut next_token = if !settings.split_prompt {
         // Generate response in a single batch if not splitting.
         let input = Tensor::new(prompt_tokens.as_slice(), device)?.unsqueeze(0)?;
         let logits = model.forward(&input, 0)?;
@@ -162,13 +161,13 @@ pub fn prompt_model(
         let input = Tensor::new(&[next_token], device)?.unsqueeze(0)?;
         let logits = model.forward(&input, prompt_tokens.len() + index)?;
         let logits = logits.squeeze(0)?;
-        let logits = if REPEAT_PENALTY == 1. {
+        let logits = if settings.repeat_penalty == 1. {
             logits
         } else {
-            let start_at = all_tokens.len().saturating_sub(REPEAT_LAST_N);
+            let start_at = all_tokens.len().saturating_sub(settings.repeat_last_n);
             candle_transformers::utils::apply_repeat_penalty(
                 &logits,
-                REPEAT_PENALTY,
+                settings.repeat_penalty,
                 &all_tokens[start_at..],
             )?
         };
@@ -185,7 +184,7 @@ pub fn prompt_model(
     }
     
     let dt = start_post_prompt.elapsed();
-    if VERBOSE_PROMPT {
+    if settings.verbose_prompt {
         // Optionally print the final output and performance stats if verbose logging is enabled.
         if let Some(rest) = tos.decode_rest().map_err(Error::msg)? {
             print!("{rest}");
@@ -215,7 +214,7 @@ pub fn prompt_model(
 /// # Returns
 /// A `Result` indicating success or any error during flushing.
 fn flush_token(token: &str) -> Result<()> {
-    if VERBOSE_PROMPT {
+    if config().generation.verbose_prompt {
         print!("{token}");
         std::io::stdout().flush()?;
     }
@@ -1,17 +1,26 @@
 use crate::{
-    config::{DOCS_TO_EMBEDD_FOLDER, DOCS_TO_TRANSLATE_FOLDER, TRANSLATE}, 
-    controllers::{keyword_decorator::decorate_passages, translator::translate}, docs::loader::load_data
+    cli::{Cli, Command},
+    config::{config, init_config, Config},
+    controllers::{embdding_ft_dataset_generator::generate_questions, keyword_decorator::decorate_passages, pipeline::{run_pipeline, PipelineSpec}, planner::{input_folder, plan, print_plan}, translator::translate},
+    docs::loader::{load_data, stream_data}
 };
-use anyhow::Result;
-use config::EMBEDD;
+use clap::Parser;
 
 mod llm;
+mod cli;
 mod config;
 mod docs;
 mod util;
 mod controllers;
 
 fn main() {
+    let cli = Cli::parse();
+    let loaded_config = match Config::load(cli.config.as_deref(), &cli.overrides) {
+        Ok(c) => c,
+        Err(e) => panic!("Error loading config: {:#?}", e),
+    };
+    init_config(loaded_config);
+
     println!(
         "avx: {}, neon: {}, simd128: {}, f16c: {}",
         candle_core::utils::with_avx(),
@@ -20,23 +29,58 @@ fn main() {
         candle_core::utils::with_f16c()
     );
 
-    
-    if TRANSLATE {
-        println!("Loading translation docs...");
-        let docs = match load_data(DOCS_TO_TRANSLATE_FOLDER) {
-            Ok(i) => i,
-            Err(e) => panic!("Error loading doc: {:#?}", e),
-        };
-        translate(docs);
-    }
-    
-    if EMBEDD {
-        println!("Loading embedding docs...");
-        let docs = match load_data(DOCS_TO_EMBEDD_FOLDER) {
-            Ok(i) => i,
-            Err(e) => panic!("Error loading docs: {:#?}", e),
-        };
-        decorate_passages(docs);
+    match cli.command {
+        Command::Translate => {
+            println!("Loading translation docs...");
+            let docs = match stream_data(&config().translate.input_folder) {
+                Ok(i) => i,
+                Err(e) => panic!("Error loading doc: {:#?}", e),
+            };
+            translate(docs);
+        },
+        Command::Decorate => {
+            println!("Loading decoration docs...");
+            let docs = match stream_data(&config().decorate.input_folder) {
+                Ok(i) => i,
+                Err(e) => panic!("Error loading docs: {:#?}", e),
+            };
+            decorate_passages(docs, false);
+        },
+        Command::Embed => {
+            println!("Loading embedding docs...");
+            let docs = match stream_data(&config().decorate.input_folder) {
+                Ok(i) => i,
+                Err(e) => panic!("Error loading docs: {:#?}", e),
+            };
+            decorate_passages(docs, true);
+        },
+        Command::GenerateQuestions => {
+            println!("Loading question generation docs...");
+            let docs = match stream_data(&config().questions.input_folder) {
+                Ok(i) => i,
+                Err(e) => panic!("Error loading docs: {:#?}", e),
+            };
+            generate_questions(docs);
+        },
+        Command::Plan { target } => {
+            println!("Loading docs to plan...");
+            let docs = match load_data(&input_folder(target)) {
+                Ok(i) => i,
+                Err(e) => panic!("Error loading docs: {:#?}", e),
+            };
+            match plan(&docs, target) {
+                Ok(plans) => print_plan(&plans, target),
+                Err(e) => panic!("Planning failed: {:#?}", e),
+            }
+        },
+        Command::Pipeline { spec, stages } => {
+            let spec = match PipelineSpec::from_file(&spec) {
+                Ok(s) => s,
+                Err(e) => panic!("Error loading pipeline spec: {:#?}", e),
+            };
+            if let Err(e) = run_pipeline(&spec, &stages) {
+                panic!("Pipeline failed: {:#?}", e);
+            }
+        },
     }
-
-}
\ No newline at end of file
+}
@@ -4,23 +4,34 @@ use std::{fs::File, io::{BufWriter, Read, Write}, sync::Mutex};
 use anyhow::Result;
 use serde::{Serialize, Deserialize};
 
-use crate::config::{FILES_TO_PROCESS, PAR_CHUNK_SIZE};
+use crate::config::config;
 
 static MULTI_PROGRESS: Lazy<Mutex<MultiProgress>> = Lazy::new(|| Mutex::new(MultiProgress::new()));
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Progress {
     pub batches_done: u64,
+    // Only the resume position is saved, sizes always come from the config
+    #[serde(skip, default = "par_chunk_size")]
     pub par_chunk_size: u64,
+    #[serde(skip, default = "files_to_process")]
     pub files_to_process: Option<usize>,
 }
 
+fn par_chunk_size() -> u64 {
+    config().progress.par_chunk_size
+}
+
+fn files_to_process() -> Option<usize> {
+    config().progress.files_to_process
+}
+
 impl Default for Progress {
     fn default() -> Self {
         Self { 
             batches_done: 0, 
-            par_chunk_size: PAR_CHUNK_SIZE, 
-            files_to_process: FILES_TO_PROCESS 
+            par_chunk_size: par_chunk_size(), 
+            files_to_process: files_to_process() 
         }
     }
 }
@@ -79,4 +90,22 @@ pub fn get_progress_bar(len: usize, style: i32) -> ProgressBar {
 
     let mp = MULTI_PROGRESS.lock().unwrap();
     mp.add(progress_bar)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_files_only_keep_the_resume_position() {
+        let file = std::env::temp_dir().join(format!("progress_test_{}.json", std::process::id()));
+        let file = file.to_string_lossy();
+        std::fs::write(file.as_ref(), r#"{"batches_done":3,"par_chunk_size":999,"files_to_process":7}"#).unwrap();
+        let progress = load_progress(&file);
+        assert_eq!(progress.batches_done, 3);
+        assert_eq!(progress.par_chunk_size, config().progress.par_chunk_size);
+        assert_eq!(progress.files_to_process, config().progress.files_to_process);
+
+        save_progress(&progress, &file).unwrap();
+        assert_eq!(std::fs::read_to_string(file.as_ref()).unwrap(), r#"{"batches_done":3}"#);
+    }
+}